@@ -1,8 +1,17 @@
-use std::{collections::BTreeMap, rc::Rc};
+use std::{cell::Cell, collections::BTreeMap, ops::Range, rc::Rc};
 
 use TSPL::Parser;
 
-use crate::run::{AgentId, InteractionSystem, Net, Tree, VarId};
+use crate::{
+    reduce::ReduceOptions,
+    report::Format,
+    run::{AgentId, InteractionSystem, Net, Tree, VarId},
+    syntax,
+};
+
+/// The agents that every program has: the annotation `:`, the annotator
+/// `::`, the antitype `~` and the type copier `&`. Their rules are built in.
+pub const BUILTIN_AGENTS: [&str; 4] = [":", "::", "~", "&"];
 
 #[derive(Debug, Clone)]
 pub struct Definition {
@@ -10,20 +19,66 @@ pub struct Definition {
     pub left_children: Vec<Tree>,
     pub right_id: AgentId,
     pub right_children: Vec<Tree>,
+    /// Location of the rule in the source.
+    pub span: Range<usize>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Vars {
     pub net: Net,
     pub var_scope: BTreeMap<String, VarId>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Level {
     pub agent_scope: BTreeMap<String, AgentId>,
     pub vars: Vars,
 }
 
+#[derive(Debug)]
+pub enum BlockKind {
+    Reduce(ReduceOptions),
+    Check,
+}
+
+/// A `Reduce` or `Check` block, parsed but not executed yet.
+#[derive(Debug)]
+pub struct PendingBlock {
+    pub kind: BlockKind,
+    pub level: Level,
+    /// Number of rules defined above the block.
+    pub rules_before: usize,
+    /// Location of the block in the source.
+    pub span: Range<usize>,
+}
+
+/// A `Derive[T for U]` declaration, kept to check that `U`'s auxiliary
+/// ports hold types that implement `T` too.
+#[derive(Debug, Clone)]
+pub struct Derived {
+    pub trait_name: String,
+    pub ty: String,
+    pub span: Range<usize>,
+}
+
+/// An `Impl[T for U { ... }]` block.
+#[derive(Debug, Clone)]
+pub struct Impl {
+    pub trait_name: String,
+    pub ty: String,
+    /// Indices of the rules defined in the block.
+    pub rules: Range<usize>,
+    pub span: Range<usize>,
+}
+
+/// A `Sub[A <= B]` declaration.
+#[derive(Debug, Clone)]
+pub struct Subtype {
+    pub sub: String,
+    pub sup: String,
+    pub span: Range<usize>,
+}
+
 #[derive(Debug)]
 pub struct ProgramBuilder<'i> {
     pub input: &'i str,
@@ -34,19 +89,46 @@ pub struct ProgramBuilder<'i> {
     pub arities: BTreeMap<AgentId, u64>,
     pub next_agent_id: u64,
     pub interaction_system: Option<Rc<InteractionSystem>>,
+    pub format: Format,
+    /// `Reduce` and `Check` blocks, in source order, waiting for `run_pending`.
+    pub pending: Vec<PendingBlock>,
+    /// Whether each block only sees the rules defined above it.
+    pub rules_so_far: bool,
+    /// Rules named in `Trace[A B]` macros, whose checking nets get printed.
+    pub traced_rules: Vec<(String, String)>,
+    /// Whether to print the checking net of every rule.
+    pub trace_all_rules: bool,
+    /// Whether gaps in typing coverage are errors rather than warnings.
+    pub strict_typing: bool,
+    /// Declarations that stand for rules, such as signatures, with the rules
+    /// they were turned into.
+    pub desugared: Vec<(String, String)>,
+    /// Constructors of the types declared with `Data`, with the types of
+    /// their auxiliary ports.
+    pub data_types: BTreeMap<String, Vec<(String, Vec<syntax::Tree>)>>,
+    pub derived: Vec<Derived>,
+    /// Traits declared with `Trait`.
+    pub traits: Vec<String>,
+    pub impls: Vec<Impl>,
+    pub subtypes: Vec<Subtype>,
+    /// Number of diagnostics with `Severity::Error` emitted so far, which
+    /// decides the exit code.
+    pub errors: Cell<usize>,
+    /// Locations of the agents' first occurrences in the source.
+    pub agent_spans: BTreeMap<String, Range<usize>>,
+    /// Locations of active pairs between two cells, with the pair's agents.
+    pub redex_spans: Vec<((AgentId, AgentId), Range<usize>)>,
 }
 impl<'i> ProgramBuilder<'i> {
     pub fn agent_list<'a>(&'a self) -> impl Iterator<Item = AgentId> + 'a {
         self.levels
             .iter()
-            .map(|x| x.agent_scope.values())
-            .flatten()
-            .map(|x| *x)
+            .flat_map(|x| x.agent_scope.values())
+            .copied()
             .chain(
                 self.levels
                     .iter()
-                    .map(|x| x.agent_scope.values())
-                    .flatten()
+                    .flat_map(|x| x.agent_scope.values())
                     .map(|x| self.agent_inverse(*x)),
             )
     }
@@ -100,7 +182,16 @@ impl<'i> ProgramBuilder<'i> {
                 return Some(*a);
             }
         }
-        return None;
+        None
+    }
+    /// Like `get_agent_id`, but also resolves cosymbols such as `~Bool`.
+    pub fn lookup_agent(&self, n: &str) -> Option<AgentId> {
+        match n.strip_prefix("~") {
+            Some(rest) if !rest.is_empty() => {
+                self.lookup_agent(rest).map(|x| self.agent_inverse(x))
+            }
+            _ => self.get_agent_id(n),
+        }
     }
     pub fn get_var_id(&self, n: &str) -> Option<VarId> {
         for level in &self.levels {
@@ -108,7 +199,7 @@ impl<'i> ProgramBuilder<'i> {
                 return Some(*a);
             }
         }
-        return None;
+        None
     }
     pub fn get_or_new_var_id(&mut self, n: String) -> VarId {
         if let Some(id) = self.get_var_id(&n) {
@@ -128,6 +219,14 @@ impl<'i> ProgramBuilder<'i> {
             break;
         }
     }
+    /// The 1-based line number of a byte offset into the input.
+    pub fn line_of(&self, offset: usize) -> Option<usize> {
+        Some(self.input.get(..offset)?.matches('\n').count() + 1)
+    }
+    /// The span from `start` to the current index, without trailing whitespace.
+    pub fn span_from(&self, start: usize) -> Range<usize> {
+        start..self.input[..self.index].trim_end().len().max(start)
+    }
     pub fn agent_inverse(&self, AgentId(a, b): AgentId) -> AgentId {
         AgentId(a, (b + 1) % 2)
     }
@@ -1,11 +1,108 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+    rc::Rc,
+};
 
 use crate::{
-    builder::ProgramBuilder,
-    run::{AgentId, Net, Tree},
+    builder::{BUILTIN_AGENTS, Definition, ProgramBuilder},
+    json::Json,
+    reduce::{Trace, TraceObserver},
+    report::{Diagnostic, Severity},
+    run::{AgentId, InteractionSystem, Net, Strategy, Tree, VarId},
 };
 
+/// The types of an agent's ports.
+#[derive(Clone, Debug)]
+pub struct Signature {
+    pub principal: Tree,
+    /// `None` for auxiliary ports that the annotator rule doesn't annotate.
+    pub aux: Vec<Option<Tree>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cell {
+    Defined,
+    /// There is no rule, but the agents' types interact.
+    Missing,
+    /// There is no rule, and the agents' types don't interact either.
+    Unneeded,
+}
+
+/// Coverage matrix of a type: `cells[i][j]` tells whether `destructors[i]`
+/// has a rule with `constructors[j]`.
+#[derive(Clone, Debug)]
+pub struct Exhaustiveness {
+    pub ty: AgentId,
+    pub constructors: Vec<AgentId>,
+    pub destructors: Vec<AgentId>,
+    pub cells: Vec<Vec<Cell>>,
+}
+
+/// A pair of agents whose types interact, but which have no rule.
+#[derive(Clone, Debug)]
+pub struct MissingRule {
+    /// The type constructors whose rule requires this one.
+    pub types: (AgentId, AgentId),
+    pub agents: (AgentId, AgentId),
+    /// The agents' full principal types.
+    pub agent_types: (Tree, Tree),
+}
+
+/// Names the free variables of some types `a`, `b`, `c`, and so on, in
+/// order of appearance.
+pub fn name_type_vars<'a>(trees: impl IntoIterator<Item = &'a Tree>) -> BTreeMap<VarId, String> {
+    fn go(tree: &Tree, names: &mut BTreeMap<VarId, String>) {
+        match tree {
+            Tree::Agent { aux, .. } => aux.iter().for_each(|x| go(x, names)),
+            Tree::Var { id } => {
+                if !names.contains_key(id) {
+                    let n = names.len();
+                    let letter = (b'a' + (n % 26) as u8) as char;
+                    let name = if n < 26 {
+                        letter.to_string()
+                    } else {
+                        format!("{}{}", letter, n / 26)
+                    };
+                    names.insert(*id, name);
+                }
+            }
+        }
+    }
+    let mut names = BTreeMap::new();
+    for tree in trees {
+        go(tree, &mut names);
+    }
+    names
+}
+
+/// Maximum number of interactions spent type-checking a single net.
+pub const CHECK_FUEL: u64 = 1_000_000;
+
+/// The pairs that make up the annotated version of a net, each with a
+/// description of the part of the original net it comes from.
+#[derive(Default)]
+struct Annotated {
+    net: Net,
+    /// `::(w) = A(p0 ... pn)` for every cell `A`.
+    cells: Vec<(Tree, Tree, String)>,
+    /// Connections between annotated cells, one per edge of the original net.
+    edges: Vec<(Tree, Tree, String)>,
+    /// Occurrences of each variable of the original net.
+    wires: BTreeMap<VarId, Vec<(Tree, String)>>,
+    /// Holes of the original net, with the variable that receives the
+    /// annotation their surroundings expect.
+    holes: Vec<(AgentId, VarId)>,
+}
+
 impl<'i> ProgramBuilder<'i> {
+    /// Location of the user-defined rule between `a` and `b`, if there is one.
+    pub fn rule_span(&self, a: AgentId, b: AgentId) -> Option<Range<usize>> {
+        self.def
+            .iter()
+            .find(|x| (x.left_id, x.right_id) == (a, b) || (x.left_id, x.right_id) == (b, a))
+            .map(|x| x.span.clone())
+    }
     pub fn can_connect_to_set(&self, set: BTreeSet<AgentId>) -> impl Iterator<Item = AgentId> + '_ {
         let system = self.interaction_system.as_ref().unwrap().clone();
         self.agent_list()
@@ -22,215 +119,1034 @@ impl<'i> ProgramBuilder<'i> {
                 return false;
             }
         }
-        return true;
+        true
     }
-    pub fn get_type_of(&self, agent: AgentId) -> Option<AgentId> {
-        let mut net = Net::default();
-        net.system = self.interaction_system.as_ref().unwrap().clone();
+    /// Annotates a cell of `agent` with fresh variables in its auxiliary
+    /// ports, returning the reduced net, the variable holding the type of
+    /// the principal port, and the auxiliary ports' variables.
+    fn annotate_agent(&self, agent: AgentId) -> (Net, VarId, Vec<VarId>) {
+        let annotation = self.get_agent_id(":").unwrap();
+        let annotator = self.get_agent_id("::").unwrap();
+        let mut net = Net {
+            system: self.interaction_system.as_ref().unwrap().clone(),
+            ..Default::default()
+        };
         let type_var = net.new_var();
         let value_var = net.new_var();
+        let arity = self.arities.get(&agent).copied().unwrap_or(0);
+        let ports: Vec<_> = (0..arity).map(|_| net.new_var()).collect();
         net.interact(
             Tree::Agent {
-                id: self.get_agent_id("::").unwrap(),
+                id: annotator,
                 aux: vec![Tree::Agent {
-                    id: self.get_agent_id(":").unwrap(),
+                    id: annotation,
                     aux: vec![Tree::Var { id: value_var }, Tree::Var { id: type_var }],
+                    origin: None,
                 }],
+                origin: None,
             },
             Tree::Agent {
                 id: agent,
-                aux: vec![],
+                aux: ports.iter().map(|id| Tree::Var { id: *id }).collect(),
+                origin: None,
             },
         );
-        net.normal();
+        net.reduce(Strategy::Lifo, Some(CHECK_FUEL), &mut ());
+        (net, type_var, ports)
+    }
+    /// Looks up the types of all of an agent's ports, by annotating a cell
+    /// with fresh variables in its auxiliary ports.
+    pub fn get_signature(&self, agent: AgentId) -> Option<Signature> {
+        let annotation = self.get_agent_id(":").unwrap();
+        let annotator = self.get_agent_id("::").unwrap();
+        let (net, type_var, ports) = self.annotate_agent(agent);
+        if !net.stuck.is_empty() || !net.interactions.is_empty() {
+            return None;
+        }
 
-        if let Tree::Agent {
-            id: type_id,
-            aux: _,
-        } = net.substitute(Tree::Var { id: type_var })
-        {
-            return Some(type_id);
-        } else {
+        let principal = net.substitute_ref(&Tree::Var { id: type_var });
+        if let Tree::Var { .. } = principal {
             return None;
         }
+        let aux = ports
+            .iter()
+            .map(|port| match net.substitute_ref(&Tree::Var { id: *port }) {
+                Tree::Agent { id, mut aux, .. } if id == annotator && aux.len() == 1 => {
+                    match aux.pop().unwrap() {
+                        Tree::Agent { id, mut aux, .. } if id == annotation && aux.len() == 2 => {
+                            aux.pop()
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            })
+            .collect();
+        Some(Signature { principal, aux })
+    }
+    pub fn get_type_of(&self, agent: AgentId) -> Option<AgentId> {
+        match self.get_signature(agent)?.principal {
+            Tree::Agent { id, .. } => Some(id),
+            Tree::Var { .. } => None,
+        }
+    }
+    /// Shows a signature as `Type(aux types)`, where `_` stands for ports
+    /// that aren't annotated. The parentheses are left out for agents
+    /// without auxiliary ports, unless the principal type has arguments.
+    pub fn show_signature(&self, signature: &Signature) -> String {
+        let net = Net::default();
+        let mut scope = name_type_vars(
+            std::iter::once(&signature.principal).chain(signature.aux.iter().flatten()),
+        );
+        let mut s = self.show_tree(&net, &mut scope, &signature.principal);
+        let has_arguments =
+            matches!(&signature.principal, Tree::Agent { aux, .. } if !aux.is_empty());
+        if !signature.aux.is_empty() || has_arguments {
+            let aux: Vec<_> = signature
+                .aux
+                .iter()
+                .map(|x| match x {
+                    Some(x) => self.show_tree(&net, &mut scope, x),
+                    None => "_".to_string(),
+                })
+                .collect();
+            s += &format!("({})", aux.join(" "));
+        }
+        s
+    }
+    /// Whether two types interact without getting stuck, including their
+    /// arguments. `List(Bot)` and `~List(t)` do, but `List(Bool)` and
+    /// `~List(Nat)` don't.
+    pub fn types_interact(&self, a: &Tree, b: &Tree) -> bool {
+        let mut net = Net {
+            system: self.interaction_system.as_ref().unwrap().clone(),
+            ..Default::default()
+        };
+        let a = net.instantiate(a);
+        let b = net.instantiate(b);
+        net.interactions.push((a, b));
+        net.reduce(Strategy::Lifo, Some(CHECK_FUEL), &mut ());
+        // A pair of types that doesn't normalize is assumed to interact.
+        net.stuck.is_empty()
     }
-    pub fn check_completeness(&mut self) {
-        let mut instances: BTreeMap<AgentId, Vec<AgentId>> = BTreeMap::new();
+    /// Instances of each type constructor, with their full types.
+    pub fn instances_by_type(&self) -> BTreeMap<AgentId, Vec<(AgentId, Tree)>> {
+        let mut instances: BTreeMap<AgentId, Vec<(AgentId, Tree)>> = BTreeMap::new();
         for i in self.agent_list() {
-            if let Some(t) = self.get_type_of(i) {
-                instances.entry(t).or_default().push(i);
-            }
-        }
-        let empty = vec![];
-        for ta in self.agent_list() {
-            for tb in self.agent_list() {
-                if ta <= tb && self.interaction_system.as_ref().unwrap().has_rule(ta, tb) {
-                    for a in instances.get(&ta).unwrap_or(&empty) {
-                        for b in instances.get(&tb).unwrap_or(&empty) {
-                            if a <= b && !self.interaction_system.as_ref().unwrap().has_rule(*a, *b)
-                            {
-                                println!(
-                                    "Completeness check failed:\n\
-		                			\tInteraction {a_ty} ~ {b_ty} is defined\n\
-		                			\t{a_val}: {a_ty}\n\
-		                			\t{b_val}: {b_ty}\n\
-		                			\tbut interaction {a_val} ~ {b_val} isn't",
-                                    a_val = self.agent_scope_back.get(&a).unwrap(),
-                                    b_val = self.agent_scope_back.get(&b).unwrap(),
-                                    a_ty = self.agent_scope_back.get(&ta).unwrap(),
-                                    b_ty = self.agent_scope_back.get(&tb).unwrap(),
-                                );
-                            }
+            if let Some(signature) = self.get_signature(i)
+                && let Tree::Agent { id, .. } = signature.principal
+            {
+                instances
+                    .entry(id)
+                    .or_default()
+                    .push((i, signature.principal));
+            }
+        }
+        instances
+    }
+    /// For every type, which of its constructors each of its destructors
+    /// has rules with. The destructors of `T` are the instances of every
+    /// type that has a rule with `T`, such as `~T`, or `~Erasure` given
+    /// `~Erasure ~ Bool`.
+    pub fn exhaustiveness(&self) -> Vec<Exhaustiveness> {
+        let instances = self.instances_by_type();
+        let system = self.interaction_system.as_ref().unwrap().clone();
+        let mut matrices = vec![];
+        for (ty, constructors) in &instances {
+            if ty.1 != 0 {
+                continue;
+            }
+            let mut destructors = vec![];
+            let mut cells = vec![];
+            for (other, instances) in &instances {
+                if !system.has_rule(*ty, *other) {
+                    continue;
+                }
+                for (d, d_type) in instances {
+                    destructors.push(*d);
+                    cells.push(
+                        constructors
+                            .iter()
+                            .map(|(c, c_type)| {
+                                if system.has_rule(*c, *d) {
+                                    Cell::Defined
+                                } else if self.types_interact(c_type, d_type) {
+                                    Cell::Missing
+                                } else {
+                                    Cell::Unneeded
+                                }
+                            })
+                            .collect(),
+                    );
+                }
+            }
+            // Nothing consumes kinds such as `Type`, so they have no matrix.
+            if destructors.is_empty() {
+                continue;
+            }
+            matrices.push(Exhaustiveness {
+                ty: *ty,
+                constructors: constructors.iter().map(|(c, _)| *c).collect(),
+                destructors,
+                cells,
+            });
+        }
+        matrices
+    }
+    /// Every pair of agents that needs a rule because their types have one,
+    /// but doesn't have one.
+    pub fn missing_rules(&self) -> Vec<MissingRule> {
+        let mut missing = vec![];
+        let instances = self.instances_by_type();
+        let system = self.interaction_system.as_ref().unwrap().clone();
+        let types: Vec<_> = instances.keys().copied().collect();
+        for (i, ta) in types.iter().enumerate() {
+            for tb in &types[i..] {
+                if !system.has_rule(*ta, *tb) {
+                    continue;
+                }
+                for (j, (a, a_type)) in instances[ta].iter().enumerate() {
+                    // Within a single type, visit each unordered pair once.
+                    let others = if ta == tb {
+                        &instances[tb][j..]
+                    } else {
+                        &instances[tb][..]
+                    };
+                    for (b, b_type) in others {
+                        if !system.has_rule(*a, *b) && self.types_interact(a_type, b_type) {
+                            missing.push(MissingRule {
+                                types: (*ta, *tb),
+                                agents: (*a, *b),
+                                agent_types: (a_type.clone(), b_type.clone()),
+                            });
                         }
                     }
                 }
             }
         }
+        missing
+    }
+    pub fn check_completeness(&mut self) -> Vec<Diagnostic> {
+        let net = Net::default();
+        self.missing_rules()
+            .into_iter()
+            // `check_impls` reports these for each `Impl` block.
+            .filter(|missing| !self.in_impl(missing.types.0, missing.types.1))
+            .map(|missing| {
+                let (ta, tb) = missing.types;
+                let (a, b) = missing.agents;
+                let (a_type, b_type) = &missing.agent_types;
+                let a_val = self.agent_scope_back.get(&a).unwrap();
+                let b_val = self.agent_scope_back.get(&b).unwrap();
+                let a_ty = self.show_tree(&net, &mut name_type_vars([a_type]), a_type);
+                let b_ty = self.show_tree(&net, &mut name_type_vars([b_type]), b_type);
+                let ta_name = self.agent_scope_back.get(&ta).unwrap();
+                let tb_name = self.agent_scope_back.get(&tb).unwrap();
+                Diagnostic::new(
+                    "completeness",
+                    format!(
+                        "Completeness check failed:\n\
+		                			\tInteraction {ta_name} ~ {tb_name} is defined\n\
+		                			\t{a_val}: {a_ty}\n\
+		                			\t{b_val}: {b_ty}\n\
+		                			\tbut interaction {a_val} ~ {b_val} isn't",
+                    ),
+                )
+                .at(self
+                    .rule_span(ta, tb)
+                    .or_else(|| self.agent_spans.get(a_val).cloned()))
+                .with("types", (a_ty.as_str(), b_ty.as_str()))
+                .with("agents", (a_val.as_str(), b_val.as_str()))
+            })
+            .collect()
     }
-    pub fn check_inverse(&mut self) {
+    /// Every violation of "`A ~ B` and `~B ~ C` imply `A ~ C`", grouped by
+    /// the missing rule `A ~ C`. Each group holds the `B`s that derive it.
+    pub fn inverse_violations(&self) -> BTreeMap<(AgentId, AgentId), Vec<AgentId>> {
+        let mut violations: BTreeMap<_, Vec<_>> = BTreeMap::new();
         let system = self.interaction_system.as_ref().unwrap().clone();
-        for a in self.agent_list() {
+        // `&` copies any agent, but its rules don't make it a subtype of anything.
+        let copier = self.get_agent_id("&");
+        for a in self.agent_list().filter(|x| Some(*x) != copier) {
             for b in self.can_connect_to(a) {
-                let b = self.agent_inverse(b);
-                for c in self.can_connect_to(b) {
-                    if !system.has_rule(a, c) {
-                        println!(
-                            "Inverse check failed:\n\
-	                			\tInteraction {a} ~ {b} is defined\n\
-	                			\tInteraction {b_inv} ~ {c} is defined\n\
-	                			\twhich means {c} <= {b}, {a} <= {b_inv}\n\
-	                			\tbut interaction {a} ~ {c} isn't defined",
-                            a = self.agent_scope_back.get(&a).unwrap(),
-                            b = self.agent_scope_back.get(&self.agent_inverse(b)).unwrap(),
-                            b_inv = self.agent_scope_back.get(&b).unwrap(),
-                            c = self.agent_scope_back.get(&c).unwrap(),
-                        );
+                for c in self.can_connect_to(self.agent_inverse(b)) {
+                    // `C ~ ~B` and `B ~ A` give the same violation, backwards.
+                    if a <= c && Some(c) != copier && !system.has_rule(a, c) {
+                        let derivations = violations.entry((a, c)).or_default();
+                        if !derivations.contains(&b) {
+                            derivations.push(b);
+                        }
                     }
                 }
             }
         }
+        violations
     }
-    pub fn check_well_typedness(&self) {
-        for def in self.def.iter() {
-            let mut show = false;
-
-            let annotation = self.get_agent_id(":").unwrap();
-            let annotator = self.get_agent_id("::").unwrap();
-            let antitype_agent = self.get_agent_id("~").unwrap();
-            if def.left_id == annotation
-                || def.right_id == annotation
-                || def.left_id == annotator
-                || def.right_id == annotator
-            {
-                continue;
+    pub fn check_inverse(&mut self) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        let name = |x: AgentId| self.show_agent(x);
+        for ((a, c), derivations) in self.inverse_violations() {
+            let mut message = format!(
+                "Inverse check failed:\n\
+                \tInteraction {} ~ {} isn't defined, but it follows from",
+                name(a),
+                name(c)
+            );
+            let mut defined = vec![];
+            for b in &derivations {
+                let b_inv = self.agent_inverse(*b);
+                message += &format!(
+                    "\n\t\t{a} ~ {b} and {b_inv} ~ {c}, which mean {a} <= {b_inv} and {c} <= {b}",
+                    a = name(a),
+                    b = name(*b),
+                    b_inv = name(b_inv),
+                    c = name(c),
+                );
+                defined.push(Json::from(vec![
+                    Json::from((name(a), name(*b))),
+                    Json::from((name(b_inv), name(c))),
+                ]));
             }
-            let make_tree = |id| Tree::Var { id };
-            let _make_ann = |v, t| Tree::Agent {
-                id: annotation,
-                aux: vec![v, t],
+            // Dropping a rule only helps if every derivation goes through it.
+            let extra = match derivations.as_slice() {
+                [b] => vec![(a, *b), (self.agent_inverse(*b), c)],
+                _ => vec![],
             };
-            let make_annotator = |v| Tree::Agent {
-                id: annotator,
-                aux: vec![v],
-            };
-            let mut net = Net::default();
-            net.system = self.interaction_system.as_ref().unwrap().clone();
-            let left_vars: Vec<_> = def
-                .left_children
-                .iter()
-                .map(|_| make_tree(net.new_var()))
-                .collect();
-            let right_vars: Vec<_> = def
-                .right_children
-                .iter()
-                .map(|_| make_tree(net.new_var()))
-                .collect();
-            let v = make_tree(net.new_var());
-
-            net.interact(
-                Tree::Agent {
-                    id: def.left_id,
-                    aux: left_vars
-                        .clone()
-                        .into_iter()
-                        .map(|a| make_annotator(a))
-                        .collect(),
-                },
-                make_annotator(v.clone()),
-            );
-            net.interact(
-                Tree::Agent {
-                    id: def.right_id,
-                    aux: right_vars
-                        .clone()
-                        .into_iter()
-                        .map(|a| make_annotator(a))
-                        .collect(),
-                },
-                make_annotator(v),
+            message += &format!("\n\tDefine {} ~ {}", name(a), name(c));
+            for (x, y) in &extra {
+                message += &format!(", or remove {} ~ {}", name(*x), name(*y));
+            }
+            let b = derivations[0];
+            diagnostics.push(
+                Diagnostic::new("inverse", message)
+                    .at(self.rule_span(a, b))
+                    .with("missing", (name(a), name(c)))
+                    .with("derivations", Json::Array(defined))
+                    .with(
+                        "extra",
+                        extra
+                            .iter()
+                            .map(|(x, y)| (name(*x), name(*y)))
+                            .collect::<Vec<_>>(),
+                    ),
             );
-            net.interact(
-                Tree::Agent {
-                    id: def.left_id,
-                    aux: left_vars
-                        .into_iter()
-                        .map(|a| {
-                            make_annotator(Tree::Agent {
-                                id: antitype_agent,
-                                aux: vec![a],
-                            })
+        }
+        diagnostics
+    }
+    /// Builds the net that checks the rule `def`, as described in
+    /// EXPLANATION.md, and performs its first interactions.
+    fn rule_checking_net(&self, def: &Definition) -> Net {
+        self.rule_checking_net_with(def, self.interaction_system.as_ref().unwrap().clone())
+    }
+    /// Like `rule_checking_net`, but with the rules of `system`.
+    pub fn rule_checking_net_with(&self, def: &Definition, system: Rc<InteractionSystem>) -> Net {
+        let annotator = self.get_agent_id("::").unwrap();
+        let antitype_agent = self.get_agent_id("~").unwrap();
+        let make_tree = |id| Tree::Var { id };
+        let make_annotator = |v| Tree::Agent {
+            id: annotator,
+            aux: vec![v],
+            origin: None,
+        };
+        let mut net = Net {
+            system,
+            ..Default::default()
+        };
+        let left_vars: Vec<_> = def
+            .left_children
+            .iter()
+            .map(|_| make_tree(net.new_var()))
+            .collect();
+        let right_vars: Vec<_> = def
+            .right_children
+            .iter()
+            .map(|_| make_tree(net.new_var()))
+            .collect();
+        let v = make_tree(net.new_var());
+
+        net.interact(
+            Tree::Agent {
+                id: def.left_id,
+                aux: left_vars.clone().into_iter().map(&make_annotator).collect(),
+                origin: None,
+            },
+            make_annotator(v.clone()),
+        );
+        net.interact(
+            Tree::Agent {
+                id: def.right_id,
+                aux: right_vars
+                    .clone()
+                    .into_iter()
+                    .map(&make_annotator)
+                    .collect(),
+                origin: None,
+            },
+            make_annotator(v),
+        );
+        net.interact(
+            Tree::Agent {
+                id: def.left_id,
+                aux: left_vars
+                    .into_iter()
+                    .map(|a| {
+                        make_annotator(Tree::Agent {
+                            id: antitype_agent,
+                            aux: vec![a],
+                            origin: None,
                         })
-                        .collect(),
-                },
-                Tree::Agent {
-                    id: def.right_id,
-                    aux: right_vars
-                        .into_iter()
-                        .map(|a| {
-                            make_annotator(Tree::Agent {
-                                id: antitype_agent,
-                                aux: vec![a],
-                            })
+                    })
+                    .collect(),
+                origin: None,
+            },
+            Tree::Agent {
+                id: def.right_id,
+                aux: right_vars
+                    .into_iter()
+                    .map(|a| {
+                        make_annotator(Tree::Agent {
+                            id: antitype_agent,
+                            aux: vec![a],
+                            origin: None,
                         })
-                        .collect(),
-                },
+                    })
+                    .collect(),
+                origin: None,
+            },
+        );
+        net
+    }
+    /// Whether `def` is a rule that defines annotations, which aren't checked.
+    /// Rules between two types, such as the `~Erasure ~ Bool` that an `Impl`
+    /// generates or the `~Int ~ Nat` of `Sub[Nat <= Int]`, count too: they
+    /// relate types rather than compute with values.
+    pub fn is_annotation_rule(&self, def: &Definition) -> bool {
+        let annotation = self.get_agent_id(":").unwrap();
+        let annotator = self.get_agent_id("::").unwrap();
+        def.left_id == annotation
+            || def.right_id == annotation
+            || def.left_id == annotator
+            || def.right_id == annotator
+            || (self.is_type(def.left_id) && self.is_type(def.right_id))
+    }
+    /// Whether `agent` is an agent of type `Type`, or the cotype of one.
+    pub fn is_type(&self, agent: AgentId) -> bool {
+        let Some(kind) = self.get_agent_id("Type") else {
+            return false;
+        };
+        [agent, self.agent_inverse(agent)]
+            .into_iter()
+            .any(|x| self.get_type_of(x) == Some(kind))
+    }
+    /// Prints the reduction of the checking net of every rule named in a
+    /// `Trace[A B]` macro, or of every rule if `trace_all_rules` is set.
+    pub fn trace_rules(&self) {
+        for (a, b) in &self.traced_rules {
+            let (Some(a_id), Some(b_id)) = (self.lookup_agent(a), self.lookup_agent(b)) else {
+                self.emit(&Diagnostic::new(
+                    "trace",
+                    format!("Can't trace rule {} ~ {}: unknown agent", a, b),
+                ));
+                continue;
+            };
+            if self.rule_span(a_id, b_id).is_none() {
+                self.emit(&Diagnostic::new(
+                    "trace",
+                    format!("Can't trace rule {} ~ {}: there is no such rule", a, b),
+                ));
+            }
+        }
+        for def in self.def.iter() {
+            let traced = self.trace_all_rules
+                || self.traced_rules.iter().any(|(a, b)| {
+                    let pair = (self.lookup_agent(a), self.lookup_agent(b));
+                    pair == (Some(def.left_id), Some(def.right_id))
+                        || pair == (Some(def.right_id), Some(def.left_id))
+                });
+            if !traced || self.is_annotation_rule(def) {
+                continue;
+            }
+            let mut net = self.rule_checking_net(def);
+            let mut scope = BTreeMap::new();
+            self.emit_rule_check(def, &net, &mut scope);
+            net.reduce(
+                Strategy::Lifo,
+                None,
+                &mut TraceObserver::new(self, &mut scope, Trace::Steps),
             );
+        }
+    }
+    /// Checks every rule that isn't an annotation rule, except the rules of
+    /// the agents in `skip`, which have been reported already.
+    pub fn check_well_typedness(&self, skip: &BTreeSet<AgentId>) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        for def in self.def.iter() {
+            let skipped = skip.contains(&def.left_id) || skip.contains(&def.right_id);
+            if self.is_annotation_rule(def) || skipped {
+                continue;
+            }
+            let mut net = self.rule_checking_net(def);
             let original_net = net.clone();
             net.normal();
 
-            if show {
-                println!("Checking a reduction...");
+            let stuck = std::mem::take(&mut net.stuck);
+            let mut holes = BTreeSet::new();
+            for (a, b) in stuck {
+                match self.stuck_hole(&net, &a, &b) {
+                    Some((hole, expected)) => {
+                        if holes.insert(hole) {
+                            diagnostics.push(self.hole_diagnostic(hole, expected));
+                        }
+                    }
+                    None => net.stuck.push((a, b)),
+                }
+            }
+            if !net.stuck.is_empty() {
+                let left = self.agent_scope_back.get(&def.left_id).unwrap();
+                let right = self.agent_scope_back.get(&def.right_id).unwrap();
                 let mut scope = BTreeMap::new();
-                let mut net = original_net.clone();
-                println!(
-                    "{}",
-                    net.show_net(&|x| self.show_agent(x), &mut scope, false)
+                let mut message = format!("Rule {} = {} is not well typed!", left, right);
+                let mut explanations = vec![];
+                for (a, b) in &net.stuck {
+                    let a_str = self.show_tree(&net, &mut scope, a);
+                    let b_str = self.show_tree(&net, &mut scope, b);
+                    message += &format!("\n\t`{} = {}` is stuck", a_str, b_str);
+                    if let Some(explanation) = self.explain_origins(&net, a, b) {
+                        message += &format!("\n\t\t{}", explanation);
+                        explanations.push(explanation);
+                    }
+                }
+                diagnostics.push(
+                    Diagnostic::new("well_typedness", message)
+                        .at(Some(def.span.clone()))
+                        .with("rule", (left.as_str(), right.as_str()))
+                        .with("explanations", explanations)
+                        .with("original", self.net_to_json(&original_net, &mut scope))
+                        .with("result", self.net_to_json(&net, &mut scope)),
                 );
-                while let Some((a, b)) = net.interactions.pop() {
-                    net.interact(a, b);
-                    print!(
-                        "----------------------------\n{}",
-                        net.show_net(&|x| self.show_agent(x), &mut scope, false)
-                    );
+            }
+        }
+        diagnostics
+    }
+    /// Describes where the cell at the root of `tree` comes from, if it was
+    /// created by a rule, as in "port 0 of `Foo` is annotated `Bool` (line 3)"
+    /// or "`Bar` expects `~Empty` (line 4)".
+    fn describe_origin(&self, net: &Net, tree: &Tree) -> Option<String> {
+        let tree = net.substitute_ref(tree);
+        let Tree::Agent {
+            id,
+            origin: Some(origin),
+            ..
+        } = &tree
+        else {
+            return None;
+        };
+        let def = self.def.get(origin.rule)?;
+        let annotator = self.get_agent_id("::").unwrap();
+        let show = |ty: &Tree| self.show_tree(&Net::default(), &mut name_type_vars([ty]), ty);
+        let description = if def.left_id == annotator || def.right_id == annotator {
+            let annotated = if def.left_id == annotator {
+                def.right_id
+            } else {
+                def.left_id
+            };
+            // The cell may have been dualized on its way, so show the type
+            // from the signature, the way it is written.
+            let signature = self.get_signature(annotated);
+            if *id == annotated {
+                format!(
+                    "`{}` comes from its annotator rule",
+                    self.show_agent(annotated)
+                )
+            } else if origin.agent == annotator {
+                let ty = signature.map(|x| x.principal).unwrap_or(tree.clone());
+                // An agent annotated with a cotype consumes its partner.
+                let verb = match ty {
+                    Tree::Agent { id, .. } if id.1 == 1 => "expects",
+                    _ => "is annotated",
+                };
+                format!("`{}` {} `{}`", self.show_agent(annotated), verb, show(&ty))
+            } else {
+                let ty = signature
+                    .and_then(|x| x.aux.get(origin.port).cloned().flatten())
+                    .unwrap_or(tree.clone());
+                format!(
+                    "port {} of `{}` is annotated `{}`",
+                    origin.port,
+                    self.show_agent(annotated),
+                    show(&ty)
+                )
+            }
+        } else {
+            format!(
+                "rule `{} ~ {}` makes `{}`",
+                self.show_agent(def.left_id),
+                self.show_agent(def.right_id),
+                self.show_agent(*id)
+            )
+        };
+        Some(match self.line_of(def.span.start) {
+            Some(line) => format!("{} (line {})", description, line),
+            None => description,
+        })
+    }
+    /// Explains a stuck pair of `net` by where its two cells come from, as in
+    /// "port 0 of `Foo` is annotated `Bool` (line 3) but `Bar` expects
+    /// `~Empty` (line 4)".
+    pub fn explain_origins(&self, net: &Net, a: &Tree, b: &Tree) -> Option<String> {
+        let parts: Vec<_> = [a, b]
+            .into_iter()
+            .filter_map(|tree| self.describe_origin(net, tree))
+            .collect();
+        if parts.is_empty() {
+            None
+        } else {
+            Some(parts.join(" but "))
+        }
+    }
+    fn describe_end(&self, names: &BTreeMap<VarId, String>, tree: &Tree) -> String {
+        match tree {
+            Tree::Agent { id, .. } => format!("`{}`", self.show_agent(*id)),
+            Tree::Var { id } => format!(
+                "`{}`",
+                names.get(id).cloned().unwrap_or(format!("?{}", id.0))
+            ),
+        }
+    }
+    /// Adds the annotated version of `tree` to `ann`, and returns the tree
+    /// that stands for its principal port. `place` describes where `tree` is
+    /// connected to.
+    fn annotate(&self, ann: &mut Annotated, tree: &Tree, place: String) -> Tree {
+        match tree {
+            Tree::Var { id } => {
+                let end = Tree::Var {
+                    id: ann.net.new_var(),
+                };
+                ann.wires.entry(*id).or_default().push((end.clone(), place));
+                end
+            }
+            Tree::Agent { id, aux, .. } => {
+                let annotator = self.get_agent_id("::").unwrap();
+                let name = self.show_agent(*id);
+                let mut ports = vec![];
+                for (k, child) in aux.iter().enumerate() {
+                    let place = format!("aux port {} of `{}`", k, name);
+                    if let Tree::Agent { id: child_id, .. } = child {
+                        let port = Tree::Var {
+                            id: ann.net.new_var(),
+                        };
+                        let child_name = self.show_agent(*child_id);
+                        let end = self.annotate(ann, child, place.clone());
+                        ann.edges.push((
+                            port.clone(),
+                            end,
+                            format!("between {} and `{}`", place, child_name),
+                        ));
+                        ports.push(port);
+                    } else {
+                        ports.push(self.annotate(ann, child, place));
+                    }
+                }
+                let w = ann.net.new_var();
+                if self.is_hole(*id) {
+                    ann.holes.push((*id, w));
+                    return Tree::Agent {
+                        id: annotator,
+                        aux: vec![Tree::Var { id: w }],
+                        origin: None,
+                    };
                 }
+                ann.cells.push((
+                    Tree::Agent {
+                        id: annotator,
+                        aux: vec![Tree::Var { id: w }],
+                        origin: None,
+                    },
+                    Tree::Agent {
+                        id: *id,
+                        aux: ports,
+                        origin: None,
+                    },
+                    format!("in the annotation of `{}`", name),
+                ));
+                Tree::Agent {
+                    id: annotator,
+                    aux: vec![Tree::Var { id: w }],
+                    origin: None,
+                }
+            }
+        }
+    }
+    /// Type-checks the active pairs of `net` by annotating every cell with
+    /// `::`, reducing the annotated net, and reporting the pairs that get
+    /// stuck. `names` gives names to the variables of `net`.
+    ///
+    /// The annotated net is built up one connection at a time, so that each
+    /// stuck pair can be blamed on the cells or wire that caused it.
+    pub fn check_net(&self, net: &Net, names: &BTreeMap<VarId, String>) -> Vec<Diagnostic> {
+        let mut ann = Annotated::default();
+        ann.net.system = net.system.clone();
+        for (a, b) in &net.interactions {
+            let (a, b) = (net.substitute_ref(a), net.substitute_ref(b));
+            let place = format!(
+                "between {} and {}",
+                self.describe_end(names, &a),
+                self.describe_end(names, &b)
+            );
+            let a_end = self.annotate(&mut ann, &a, self.describe_end(names, &b));
+            let b_end = self.annotate(&mut ann, &b, self.describe_end(names, &a));
+            ann.edges.push((a_end, b_end, place));
+        }
+        let mut wires = vec![];
+        for (id, ends) in std::mem::take(&mut ann.wires) {
+            if let [(a, a_place), (b, b_place), ..] = &ends[..] {
+                let name = names.get(&id).cloned().unwrap_or(format!("?{}", id.0));
+                wires.push((
+                    a.clone(),
+                    b.clone(),
+                    format!("on `{}`, between {} and {}", name, a_place, b_place),
+                ));
             }
+        }
 
-            if !net.stuck.is_empty() {
-                println!(
-                    "Rule {} = {} is not well typed!",
-                    self.agent_scope_back.get(&def.left_id).unwrap(),
-                    self.agent_scope_back.get(&def.right_id).unwrap()
-                );
+        let annotator = self.get_agent_id("::").unwrap();
+        let builtin: Vec<_> = BUILTIN_AGENTS
+            .into_iter()
+            .filter_map(|x| self.get_agent_id(x))
+            .collect();
+        let is_value = |x: &AgentId| !self.is_type(*x) && !builtin.contains(&AgentId(x.0, 0));
+        let is_value_pair = |x: &Tree, y: &Tree| match (x, y) {
+            (Tree::Agent { id: x, .. }, Tree::Agent { id: y, .. }) => is_value(x) && is_value(y),
+            _ => false,
+        };
+        // Each diagnostic, and whether it is about a pair of values.
+        let mut reports = vec![];
+        let mut untyped = BTreeSet::new();
+        let mut reported_holes = BTreeSet::new();
+        let holes = ann.holes;
+        let mut checking = ann.net;
+        let mut fuel = CHECK_FUEL;
+        for (a, b, place) in ann.cells.into_iter().chain(ann.edges).chain(wires) {
+            let stuck_before = checking.stuck.len();
+            checking.interactions.push((a, b));
+            fuel -= checking.reduce(Strategy::Lifo, Some(fuel), &mut ());
+            if !checking.interactions.is_empty() {
+                reports.push((
+                    false,
+                    Diagnostic::new(
+                        "fuel",
+                        format!(
+                            "Ran out of fuel after {} steps while type-checking a net",
+                            CHECK_FUEL
+                        ),
+                    ),
+                ));
+                break;
+            }
+            for (x, y) in &checking.stuck[stuck_before..] {
+                // Holes that rules create get stuck against whatever reaches
+                // them; report them like the holes written in the net.
+                if let Some((hole, expected)) = self.stuck_hole(&checking, x, y) {
+                    if reported_holes.insert(hole) {
+                        reports.push((false, self.hole_diagnostic(hole, expected)));
+                    }
+                    continue;
+                }
                 let mut scope = BTreeMap::new();
-                println!(
-                    "Original net:\n{}",
-                    original_net.show_net(&|x| self.show_agent(x), &mut scope, false)
-                );
-                println!(
-                    "----------------------------\n{}",
-                    net.show_net(&|x| self.show_agent(x), &mut scope, false)
-                );
+                let x_str = self.show_tree(&checking, &mut scope, x);
+                let y_str = self.show_tree(&checking, &mut scope, y);
+                let mut check = "net_typing";
+                let problem = match (x, y) {
+                    (Tree::Agent { id, .. }, Tree::Agent { id: other, .. })
+                    | (Tree::Agent { id: other, .. }, Tree::Agent { id, .. })
+                        if *id == annotator =>
+                    {
+                        // Report each untyped agent once, rather than once
+                        // for every cell and every annotation that meets it.
+                        if !untyped.insert(*other) {
+                            continue;
+                        }
+                        check = "untyped";
+                        if *other == annotator {
+                            "`::` doesn't annihilate with itself; add `::(a) ~ ::(a)`".to_string()
+                        } else {
+                            format!("`{}` has no type", self.show_agent(*other))
+                        }
+                    }
+                    _ => match self.explain_origins(&checking, x, y) {
+                        Some(explanation) => format!(
+                            "`{} = {}` is stuck, {}\n\t\t{}",
+                            x_str, y_str, place, explanation
+                        ),
+                        None => format!("`{} = {}` is stuck, {}", x_str, y_str, place),
+                    },
+                };
+                reports.push((
+                    is_value_pair(x, y),
+                    Diagnostic::new(check, format!("Net is not well typed:\n\t{}", problem))
+                        .with("stuck", (x_str, y_str))
+                        .with("location", place.clone()),
+                ));
+            }
+        }
+        // A pair of values gets stuck along with their types; only blame the
+        // values if no types got stuck anywhere in the net.
+        let type_error = reports
+            .iter()
+            .any(|(value, x)| !value && x.severity == Severity::Error);
+        let mut diagnostics: Vec<_> = reports
+            .into_iter()
+            .filter(|(value, _)| !(type_error && *value))
+            .map(|(_, x)| x)
+            .collect();
+        for (hole, w) in holes
+            .into_iter()
+            .filter(|(hole, _)| !reported_holes.contains(hole))
+        {
+            let expected = checking.substitute_ref(&Tree::Var { id: w });
+            diagnostics.push(self.hole_diagnostic(hole, self.expected_type(&expected)));
+        }
+        diagnostics
+    }
+    /// Whether `agent` is a typed hole, such as `?_h`, whose type gets
+    /// reported instead of checked.
+    pub fn is_hole(&self, agent: AgentId) -> bool {
+        agent.1 == 0
+            && self
+                .agent_scope_back
+                .get(&agent)
+                .is_some_and(|x| x.starts_with("?_"))
+    }
+    /// If one side of the stuck pair `a = b` is a hole, the hole and the type
+    /// it should have: the one that the annotation on the other side asks
+    /// for, or else the dual of the other agent's type.
+    fn stuck_hole(&self, net: &Net, a: &Tree, b: &Tree) -> Option<(AgentId, Option<Tree>)> {
+        let annotator = self.get_agent_id("::").unwrap();
+        let (hole, other) = [(a, b), (b, a)].into_iter().find_map(|(x, y)| match x {
+            Tree::Agent { id, .. } if self.is_hole(*id) => Some((*id, y)),
+            _ => None,
+        })?;
+        let expected = match other {
+            Tree::Agent { id, aux, .. } if *id == annotator => {
+                self.expected_type(&net.substitute_ref(&aux[0]))
+            }
+            Tree::Agent { id, .. } => self
+                .get_signature(*id)
+                .map(|x| self.dual_type(&x.principal, net.system.clone())),
+            Tree::Var { .. } => None,
+        };
+        Some((hole, expected))
+    }
+    /// The type that a hole should have, given the annotation that reaches
+    /// its annotator. `~(:(v T))` asks for a `T`; `:(v T)` is the type of
+    /// what the hole is connected to, so it asks for a `~T`.
+    fn expected_type(&self, annotation: &Tree) -> Option<Tree> {
+        let annotation_agent = self.get_agent_id(":").unwrap();
+        let antitype_agent = self.get_agent_id("~").unwrap();
+        match annotation {
+            Tree::Agent { id, aux, .. } if *id == antitype_agent => match &aux[..] {
+                [Tree::Agent { id, aux, .. }] if *id == annotation_agent => Some(aux[1].clone()),
+                _ => None,
+            },
+            Tree::Agent { id, aux, .. } if *id == annotation_agent => {
+                Some(self.dual_type(&aux[1], self.interaction_system.as_ref().unwrap().clone()))
             }
+            _ => None,
+        }
+    }
+    /// The type of what interacts with a `ty`, found by reducing `~(x) = ty`
+    /// in `system`. The dual of `List(Nat)` is `~List(~Nat)`.
+    pub fn dual_type(&self, ty: &Tree, system: Rc<InteractionSystem>) -> Tree {
+        let antitype_agent = self.get_agent_id("~").unwrap();
+        let mut net = Net {
+            system,
+            ..Default::default()
+        };
+        let ty = net.instantiate(ty);
+        let dual = net.new_var();
+        net.interactions.push((
+            Tree::Agent {
+                id: antitype_agent,
+                aux: vec![Tree::Var { id: dual }],
+                origin: None,
+            },
+            ty,
+        ));
+        net.reduce(Strategy::Lifo, Some(CHECK_FUEL), &mut ());
+        self.push_duals(net.substitute_ref(&Tree::Var { id: dual }))
+    }
+    /// Moves `~` cells towards the leaves of a type, so that `~(Nat)` becomes
+    /// `~Nat`, the way types are written.
+    pub fn push_duals(&self, ty: Tree) -> Tree {
+        let antitype_agent = self.get_agent_id("~").unwrap();
+        let dual = |x| Tree::Agent {
+            id: antitype_agent,
+            aux: vec![x],
+            origin: None,
+        };
+        match ty {
+            Tree::Agent {
+                id,
+                mut aux,
+                origin,
+            } if id == antitype_agent => match aux.pop().unwrap() {
+                Tree::Agent { id, aux, .. } if id == antitype_agent => {
+                    self.push_duals(aux.into_iter().next().unwrap())
+                }
+                Tree::Agent { id, aux, origin } => Tree::Agent {
+                    id: self.agent_inverse(id),
+                    aux: aux.into_iter().map(|x| self.push_duals(dual(x))).collect(),
+                    origin,
+                },
+                var => Tree::Agent {
+                    id,
+                    aux: vec![var],
+                    origin,
+                },
+            },
+            Tree::Agent { id, aux, origin } => Tree::Agent {
+                id,
+                aux: aux.into_iter().map(|x| self.push_duals(x)).collect(),
+                origin,
+            },
+            var => var,
+        }
+    }
+    fn hole_diagnostic(&self, hole: AgentId, expected: Option<Tree>) -> Diagnostic {
+        let name = self.show_agent(hole);
+        let short = name.trim_start_matches('?');
+        let expected =
+            expected.map(|ty| self.show_tree(&Net::default(), &mut name_type_vars([&ty]), &ty));
+        let message = match &expected {
+            Some(ty) => format!("Hole `{}` expects `{}`", short, ty),
+            None => format!("Hole `{}` can have any type", short),
+        };
+        Diagnostic::new("hole", message)
+            .severity(Severity::Warning)
+            .at(self.agent_spans.get(&name).cloned())
+            .with("hole", short)
+            .with("expects", expected.map(Json::from).unwrap_or(Json::Null))
+    }
+    /// Type-checks the active pairs written at the top level of the file.
+    pub fn check_top_level(&self) -> Vec<Diagnostic> {
+        let level = &self.levels[0];
+        let names = level
+            .vars
+            .var_scope
+            .iter()
+            .map(|(k, v)| (*v, k.clone()))
+            .collect();
+        let mut net = level.vars.net.clone();
+        net.system = self.interaction_system.clone().unwrap();
+        self.check_net(&net, &names)
+    }
+    /// Reports agents that can't be fully typed: agents without an annotator
+    /// rule, agents whose annotation gets stuck, and agents whose annotator
+    /// rule leaves some auxiliary ports unannotated. Agents that appear in
+    /// some type, such as `Type` in `Bool : Type` or `List` in `List(a)`,
+    /// aren't expected to have a type themselves, nor to annotate the type
+    /// arguments in their auxiliary ports. These are warnings unless
+    /// `strict_typing` is set.
+    pub fn check_coverage(&self) -> Vec<Diagnostic> {
+        let severity = if self.strict_typing {
+            Severity::Error
+        } else {
+            Severity::Warning
+        };
+        let special: Vec<_> = BUILTIN_AGENTS
+            .into_iter()
+            .filter_map(|x| self.get_agent_id(x))
+            .collect();
+        let annotator = self.get_agent_id("::").unwrap();
+        let system = self.interaction_system.as_ref().unwrap().clone();
+        let agents: Vec<_> = self
+            .agent_spans
+            .keys()
+            .filter_map(|name| self.lookup_agent(name))
+            .filter(|x| !special.contains(x))
+            .collect();
+
+        let mut types = BTreeSet::new();
+        fn collect(tree: &Tree, types: &mut BTreeSet<AgentId>) {
+            if let Tree::Agent { id, aux, .. } = tree {
+                types.insert(*id);
+                for x in aux {
+                    collect(x, types);
+                }
+            }
+        }
+        let signatures: BTreeMap<_, _> = agents
+            .iter()
+            .filter_map(|x| Some((*x, self.get_signature(*x)?)))
+            .collect();
+        for signature in signatures.values() {
+            collect(&signature.principal, &mut types);
+            for port in signature.aux.iter().flatten() {
+                collect(port, &mut types);
+            }
+        }
+
+        let mut diagnostics = vec![];
+        for agent in agents {
+            let name = self.show_agent(agent);
+            let span = self
+                .rule_span(annotator, agent)
+                .or_else(|| self.agent_spans.get(&name).cloned());
+            // Type constructors are still reported if looking up their
+            // type gets stuck.
+            let lookup_ok = signatures.contains_key(&agent) || !system.has_rule(annotator, agent);
+            if types.contains(&agent) && lookup_ok {
+                continue;
+            }
+            let message = if !system.has_rule(annotator, agent) {
+                format!("`{}` has no type", name)
+            } else if let Some(signature) = signatures.get(&agent) {
+                let missing: Vec<_> = signature
+                    .aux
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, x)| x.is_none())
+                    .map(|(i, _)| i.to_string())
+                    .collect();
+                if missing.is_empty() {
+                    continue;
+                }
+                format!(
+                    "The annotator rule of `{}` doesn't annotate its auxiliary port(s) {}",
+                    name,
+                    missing.join(", ")
+                )
+            } else {
+                let (net, _, _) = self.annotate_agent(agent);
+                let mut scope = BTreeMap::new();
+                let stuck: Vec<_> = net
+                    .stuck
+                    .iter()
+                    .map(|(a, b)| {
+                        format!(
+                            "`{} = {}`",
+                            self.show_tree(&net, &mut scope, a),
+                            self.show_tree(&net, &mut scope, b)
+                        )
+                    })
+                    .collect();
+                if stuck.is_empty() {
+                    format!("Looking up the type of `{}` doesn't terminate", name)
+                } else {
+                    format!(
+                        "Looking up the type of `{}` gets stuck at {}",
+                        name,
+                        stuck.join(", ")
+                    )
+                }
+            };
+            diagnostics.push(
+                Diagnostic::new("coverage", format!("Typing coverage: {}", message))
+                    .severity(severity)
+                    .at(span)
+                    .with("agent", name),
+            );
         }
+        diagnostics
     }
 }
@@ -0,0 +1,188 @@
+//! `Data` declarations, which declare a type together with its
+//! constructors.
+
+use TSPL::Parser;
+
+use crate::{
+    builder::{Derived, ProgramBuilder},
+    report::Diagnostic,
+    syntax::Tree,
+};
+
+impl<'i> ProgramBuilder<'i> {
+    /// Parses the inside of `Data[Nat = Nat.zero | Nat.succ(~Nat)]`, up to
+    /// the closing bracket, and defines the rules it stands for:
+    ///
+    /// ```text
+    /// Nat : Type
+    /// Nat.zero : Nat
+    /// Nat.succ : Nat(~Nat)
+    /// ```
+    ///
+    /// The constructors' arguments are the types of their auxiliary ports.
+    pub fn parse_data(&mut self, start: usize) -> Result<(), String> {
+        let ty = self.parse_ctr_name()?;
+        self.skip_trivia();
+        self.consume("=")?;
+        let mut constructors = vec![];
+        loop {
+            match self.parse_tree()? {
+                Tree::Agent(name, ports) => constructors.push((name, ports)),
+                Tree::Var(name) => {
+                    return Err(format!(
+                        "Expected a constructor of `{}`, found `{}`",
+                        ty, name
+                    ));
+                }
+            }
+            self.skip_trivia();
+            if self.peek_one() != Some('|') {
+                break;
+            }
+            self.consume("|")?;
+        }
+        self.skip_trivia();
+        self.consume("]")?;
+        let kind = Tree::Agent("Type".to_string(), vec![]);
+        let (left, right) = self.signature_rule(&ty, kind, vec![])?;
+        self.define_rule(left, right, start, false)?;
+        for (name, ports) in &constructors {
+            let principal = Tree::Agent(ty.clone(), vec![]);
+            let (left, right) = self.signature_rule(name, principal, ports.clone())?;
+            self.define_rule(left, right, start, false)?;
+        }
+        self.data_types.insert(ty, constructors);
+        Ok(())
+    }
+    /// Parses the inside of `Derive[Erasure Duplication for Nat]`, up to the
+    /// closing bracket, and defines `~Erasure ~ Nat` along with the rules
+    /// that erase or duplicate each constructor of `Nat`, which must have
+    /// been declared with `Data`:
+    ///
+    /// ```text
+    /// Era ~ Nat.succ(Era)
+    /// Dup(Nat.succ(a0) Nat.succ(b0)) ~ Nat.succ(Dup(a0 b0))
+    /// ```
+    pub fn parse_derive(&mut self, start: usize) -> Result<(), String> {
+        let mut traits = vec![];
+        loop {
+            self.skip_trivia();
+            // Trait names are capitalized, so the first lowercase name ends
+            // the list.
+            if self.peek_one().is_some_and(|c| c.is_ascii_lowercase()) {
+                let keyword = self.parse_var_name()?;
+                if keyword != "for" {
+                    return Err(format!("Expected `for`, found `{}`", keyword));
+                }
+                break;
+            }
+            traits.push(self.parse_ctr_name()?);
+        }
+        let ty = self.parse_ctr_name()?;
+        self.skip_trivia();
+        self.consume("]")?;
+        let constructors = self.data_types.get(&ty).cloned().ok_or(format!(
+            "`{}` must be declared with `Data` before deriving for it",
+            ty
+        ))?;
+        let agent = |name: &str, aux| Tree::Agent(name.to_string(), aux);
+        for trait_name in traits {
+            let rules: Vec<_> = match trait_name.as_str() {
+                "Erasure" => constructors
+                    .iter()
+                    .map(|(c, ports)| {
+                        let eras = ports.iter().map(|_| agent("Era", vec![])).collect();
+                        (agent("Era", vec![]), agent(c, eras))
+                    })
+                    .collect(),
+                "Duplication" => constructors
+                    .iter()
+                    .map(|(c, ports)| {
+                        let (a, b) = (vars("a", ports.len()), vars("b", ports.len()));
+                        let dups = a
+                            .iter()
+                            .zip(&b)
+                            .map(|(a, b)| agent("Dup", vec![a.clone(), b.clone()]))
+                            .collect();
+                        (agent("Dup", vec![agent(c, a), agent(c, b)]), agent(c, dups))
+                    })
+                    .collect(),
+                _ => {
+                    return Err(format!(
+                        "Can't derive `{}`; only `Erasure` and `Duplication` can be derived",
+                        trait_name
+                    ));
+                }
+            };
+            let cotype = agent(&format!("~{}", trait_name), vec![]);
+            for (left, right) in [(cotype, agent(&ty, vec![]))].into_iter().chain(rules) {
+                self.desugared.push((
+                    format!("Derive[{} for {}]", trait_name, ty),
+                    format!("{} ~ {}", left, right),
+                ));
+                self.define_rule(left, right, start, false)?;
+            }
+            self.derived.push(Derived {
+                trait_name,
+                ty: ty.clone(),
+                span: self.span_from(start),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<'i> ProgramBuilder<'i> {
+    /// Checks that the types held in the auxiliary ports of derived types
+    /// implement the derived traits too, since the derived rules pass the
+    /// trait's agents on to them.
+    pub fn check_derived(&self) -> Vec<Diagnostic> {
+        let system = self.interaction_system.as_ref().unwrap().clone();
+        let mut diagnostics = vec![];
+        for derived in &self.derived {
+            let Some(cotype) = self.lookup_agent(&format!("~{}", derived.trait_name)) else {
+                continue;
+            };
+            let mut reported = vec![];
+            for (constructor, ports) in &self.data_types[&derived.ty] {
+                for (i, port) in ports.iter().enumerate() {
+                    let Tree::Agent(port_type, _) = port else {
+                        continue;
+                    };
+                    let Some(port_type) = self.lookup_agent(port_type) else {
+                        continue;
+                    };
+                    let held = self.agent_inverse(port_type);
+                    if system.has_rule(cotype, held) || reported.contains(&held) {
+                        continue;
+                    }
+                    reported.push(held);
+                    let held = self.show_agent(held);
+                    diagnostics.push(
+                        Diagnostic::new(
+                            "derive",
+                            format!(
+                                "Can't derive {trait_name} for {ty}:\n\
+                                \tport {i} of {constructor} holds {held}, which doesn't implement {trait_name}\n\
+                                \tDerive it, or define ~{trait_name} ~ {held}",
+                                trait_name = derived.trait_name,
+                                ty = derived.ty,
+                            ),
+                        )
+                        .at(Some(derived.span.clone()))
+                        .with("trait", derived.trait_name.as_str())
+                        .with("for", derived.ty.as_str())
+                        .with("needs", held.as_str()),
+                    );
+                }
+            }
+        }
+        diagnostics
+    }
+}
+
+fn vars(prefix: &str, n: usize) -> Vec<Tree> {
+    (0..n)
+        .map(|i| Tree::Var(format!("{}{}", prefix, i)))
+        .collect()
+}
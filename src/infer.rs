@@ -0,0 +1,343 @@
+//! Inference of annotator rules for agents that are used in rules but
+//! don't have a type.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+    rc::Rc,
+};
+
+use crate::{
+    builder::{Definition, ProgramBuilder},
+    checker::{CHECK_FUEL, Signature},
+    report::Diagnostic,
+    run::{AgentId, InteractionSystem, Net, Strategy, Tree, VarId},
+};
+
+/// The signature inferred for an agent, along with the annotator rule that
+/// gives it that signature.
+#[derive(Clone, Debug)]
+pub struct Inferred {
+    pub agent: AgentId,
+    pub signature: Signature,
+    pub rule: Definition,
+}
+
+/// An untyped agent, with a metavariable for the type of each of its ports,
+/// principal port first.
+struct Unknown {
+    agent: AgentId,
+    metavars: Vec<AgentId>,
+    span: Range<usize>,
+}
+
+impl<'i> ProgramBuilder<'i> {
+    /// The agents that need a type: those that have rules with typed agents,
+    /// but no annotator rule of their own.
+    fn untyped_agents(&self) -> BTreeSet<AgentId> {
+        let system = self.interaction_system.as_ref().unwrap().clone();
+        let annotator = self.get_agent_id("::").unwrap();
+        let typed = |x: AgentId| self.get_signature(x).is_some();
+        let mut agents = BTreeSet::new();
+        for def in &self.def {
+            if self.is_annotation_rule(def) {
+                continue;
+            }
+            for (a, b) in [(def.left_id, def.right_id), (def.right_id, def.left_id)] {
+                if a.1 == 0 && !system.has_rule(annotator, a) && typed(b) {
+                    agents.insert(a);
+                }
+            }
+        }
+        agents
+    }
+    /// The annotator rule `::(:(agent(x0 ...) principal)) ~ agent(::(:(x0 aux0)) ...)`,
+    /// where `types` holds the principal type followed by the auxiliary ones.
+    /// Auxiliary ports whose type is `None` are left unannotated.
+    fn annotator_rule(
+        &self,
+        agent: AgentId,
+        types: &[Option<Tree>],
+        span: Range<usize>,
+    ) -> Definition {
+        let annotation = self.get_agent_id(":").unwrap();
+        let annotator = self.get_agent_id("::").unwrap();
+        let annotated = |value: Tree, ty: Tree| Tree::Agent {
+            id: annotation,
+            aux: vec![value, ty],
+            origin: None,
+        };
+        let vars: Vec<_> = (0..types.len() - 1)
+            .map(|i| Tree::Var {
+                id: VarId(i as u64),
+            })
+            .collect();
+        let cell = Tree::Agent {
+            id: agent,
+            aux: vars.clone(),
+            origin: None,
+        };
+        Definition {
+            left_id: annotator,
+            left_children: vec![annotated(cell, types[0].clone().unwrap())],
+            right_id: agent,
+            right_children: vars
+                .into_iter()
+                .zip(&types[1..])
+                .map(|(var, ty)| match ty {
+                    Some(ty) => Tree::Agent {
+                        id: annotator,
+                        aux: vec![annotated(var, ty.clone())],
+                        origin: None,
+                    },
+                    None => var,
+                })
+                .collect(),
+            span,
+        }
+    }
+    /// The stuck pairs of the checking nets of the rules that `unknowns` take
+    /// part in, given annotator rules `rules` for them.
+    fn stuck_pairs(
+        &self,
+        unknowns: &[Unknown],
+        rules: Vec<Definition>,
+    ) -> (Rc<InteractionSystem>, Vec<(AgentId, Tree, Tree)>) {
+        let mut def = self.def.clone();
+        def.extend(rules);
+        let system = self.interaction_system_with(def);
+        let mut stuck = vec![];
+        for unknown in unknowns {
+            for rule in self.def.iter().filter(|x| {
+                !self.is_annotation_rule(x)
+                    && (x.left_id == unknown.agent || x.right_id == unknown.agent)
+            }) {
+                let mut net = self.rule_checking_net_with(rule, system.clone());
+                net.reduce(Strategy::Lifo, Some(CHECK_FUEL), &mut ());
+                stuck.extend(
+                    net.stuck.iter().chain(&net.interactions).map(|(a, b)| {
+                        (unknown.agent, net.substitute_ref(a), net.substitute_ref(b))
+                    }),
+                );
+            }
+        }
+        (system, stuck)
+    }
+    /// Infers a signature for every agent that has rules with typed agents
+    /// but no annotator rule.
+    ///
+    /// Each port of such an agent gets a metavariable, an agent without
+    /// rules that stands for its type. When the checking nets of the agent's
+    /// rules are reduced, the metavariables get stuck against the types that
+    /// reach them: `?m = Nat` says that `?m` must consume a `Nat`, so it is
+    /// bound to `~Nat`, the dual of `Nat`. The nets are reduced again with the
+    /// bindings in place until no more metavariables get bound. Agents whose
+    /// rules still get stuck, or whose ports are only constrained by each
+    /// other or by type variables, are reported instead, each along with the
+    /// agent; they need a signature.
+    pub fn infer_annotations(&mut self) -> (Vec<Inferred>, Vec<(AgentId, Diagnostic)>) {
+        let mut unknowns = vec![];
+        for agent in self.untyped_agents() {
+            let name = self.show_agent(agent);
+            let ports = self.arities.get(&agent).copied().unwrap_or(0) + 1;
+            let metavars = (0..ports)
+                .map(|i| {
+                    let metavar = self.new_agent_id();
+                    self.set_arity(metavar, 0);
+                    self.agent_scope_back
+                        .insert(metavar, format!("?{}.{}", name, i));
+                    metavar
+                })
+                .collect();
+            unknowns.push(Unknown {
+                agent,
+                span: self.agent_spans.get(&name).cloned().unwrap_or(0..0),
+                metavars,
+            });
+        }
+        let is_metavar = |x: AgentId| {
+            unknowns
+                .iter()
+                .any(|u| u.metavars.contains(&AgentId(x.0, 0)))
+        };
+        let mut bindings: BTreeMap<AgentId, Tree> = BTreeMap::new();
+        let resolve = |bindings: &BTreeMap<AgentId, Tree>, metavar: AgentId| {
+            self.resolve(
+                bindings,
+                &Tree::Agent {
+                    id: metavar,
+                    aux: vec![],
+                    origin: None,
+                },
+            )
+        };
+        let annotator = self.get_agent_id("::").unwrap();
+        let annotation = self.get_agent_id(":").unwrap();
+        loop {
+            let rules = unknowns
+                .iter()
+                .map(|u| {
+                    let types: Vec<_> = u
+                        .metavars
+                        .iter()
+                        .map(|m| Some(resolve(&bindings, *m)))
+                        .collect();
+                    self.annotator_rule(u.agent, &types, u.span.clone())
+                })
+                .collect();
+            let (system, stuck) = self.stuck_pairs(&unknowns, rules);
+            let mut progress = false;
+            for (_, a, b) in stuck {
+                for (x, ty) in [(&a, &b), (&b, &a)] {
+                    let Tree::Agent { id: metavar, .. } = x else {
+                        continue;
+                    };
+                    let metavar = *metavar;
+                    if !is_metavar(metavar) || bindings.contains_key(&AgentId(metavar.0, 0)) {
+                        continue;
+                    }
+                    // Only bind to types that are fully known. Metavariables
+                    // that meet each other or a type variable stay unbound.
+                    let Tree::Agent { id: other, .. } = ty else {
+                        continue;
+                    };
+                    if [annotator, annotation].contains(other) || has_vars_or(ty, &is_metavar) {
+                        continue;
+                    }
+                    // `~?m = T` binds `?m` to `T` itself.
+                    let binding = if metavar.1 == 0 {
+                        self.dual_type(ty, system.clone())
+                    } else {
+                        ty.clone()
+                    };
+                    bindings.insert(AgentId(metavar.0, 0), binding);
+                    progress = true;
+                    break;
+                }
+            }
+            if !progress {
+                break;
+            }
+        }
+        let types: Vec<Vec<Tree>> = unknowns
+            .iter()
+            .map(|u| u.metavars.iter().map(|m| resolve(&bindings, *m)).collect())
+            .collect();
+        let mut inferred = vec![];
+        let mut diagnostics = vec![];
+        let mut rules = vec![];
+        for (unknown, types) in unknowns.iter().zip(&types) {
+            let name = self.show_agent(unknown.agent);
+            let unbound: Vec<_> = types
+                .iter()
+                .enumerate()
+                .filter(|(_, ty)| has_vars_or(ty, &is_metavar))
+                .map(|(i, _)| i)
+                .collect();
+            if !unbound.is_empty() {
+                let ports: Vec<_> = unbound
+                    .iter()
+                    .map(|i| match i {
+                        0 => "the principal port".to_string(),
+                        i => format!("port {}", i - 1),
+                    })
+                    .collect();
+                diagnostics.push((
+                    unknown.agent,
+                    Diagnostic::new(
+                        "inference",
+                        format!(
+                            "The type of `{}` is ambiguous: nothing fixes the type of {}; give it a signature",
+                            name,
+                            ports.join(" and ")
+                        ),
+                    )
+                    .at(Some(unknown.span.clone())),
+                ));
+                continue;
+            }
+            let types: Vec<_> = types.iter().cloned().map(Some).collect();
+            rules.push(self.annotator_rule(unknown.agent, &types, unknown.span.clone()));
+        }
+        // Check the solutions: an agent whose rules still get stuck has
+        // conflicting constraints.
+        let (_, stuck) = self.stuck_pairs(&unknowns, rules.clone());
+        for rule in rules {
+            let agent = rule.right_id;
+            let name = self.show_agent(agent);
+            let unknown = unknowns.iter().find(|u| u.agent == agent).unwrap();
+            let conflicts: Vec<_> = stuck
+                .iter()
+                .filter(|(x, _, _)| *x == agent)
+                .map(|(_, a, b)| {
+                    let mut scope = BTreeMap::new();
+                    let net = Net::default();
+                    format!(
+                        "`{} = {}` is stuck",
+                        self.show_tree(&net, &mut scope, a),
+                        self.show_tree(&net, &mut scope, b)
+                    )
+                })
+                .collect();
+            if !conflicts.is_empty() {
+                diagnostics.push((
+                    agent,
+                    Diagnostic::new(
+                        "inference",
+                        format!(
+                            "Can't infer the type of `{}`: no signature makes its rules well typed\n\t{}",
+                            name,
+                            conflicts.join("\n\t")
+                        ),
+                    )
+                    .at(Some(unknown.span.clone())),
+                ));
+                continue;
+            }
+            let types = &types[unknowns.iter().position(|u| u.agent == agent).unwrap()];
+            inferred.push(Inferred {
+                agent,
+                signature: Signature {
+                    principal: types[0].clone(),
+                    aux: types[1..].iter().cloned().map(Some).collect(),
+                },
+                rule,
+            });
+        }
+        (inferred, diagnostics)
+    }
+    /// Replaces the bound metavariables in `tree` by their bindings, and
+    /// their cosymbols by the duals of their bindings.
+    fn resolve(&self, bindings: &BTreeMap<AgentId, Tree>, tree: &Tree) -> Tree {
+        match tree {
+            Tree::Agent { id, aux, origin } => match bindings.get(&AgentId(id.0, 0)) {
+                Some(binding) if aux.is_empty() => {
+                    let binding = self.resolve(bindings, binding);
+                    if id.1 == 0 {
+                        binding
+                    } else {
+                        self.push_duals(Tree::Agent {
+                            id: self.get_agent_id("~").unwrap(),
+                            aux: vec![binding],
+                            origin: None,
+                        })
+                    }
+                }
+                _ => Tree::Agent {
+                    id: *id,
+                    aux: aux.iter().map(|x| self.resolve(bindings, x)).collect(),
+                    origin: *origin,
+                },
+            },
+            tree => tree.clone(),
+        }
+    }
+}
+
+/// Whether `tree` has variables, or agents for which `pred` holds.
+fn has_vars_or(tree: &Tree, pred: &impl Fn(AgentId) -> bool) -> bool {
+    match tree {
+        Tree::Agent { id, aux, .. } => pred(*id) || aux.iter().any(|x| has_vars_or(x, pred)),
+        Tree::Var { .. } => true,
+    }
+}
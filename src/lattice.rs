@@ -0,0 +1,102 @@
+//! The subtyping preorder among all types, drawn as a Hasse diagram.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{builder::ProgramBuilder, run::AgentId};
+
+/// Types ordered by `is_subtype_of`. Types that are subtypes of each other
+/// are collapsed into a single class.
+#[derive(Clone, Debug)]
+pub struct Lattice {
+    pub classes: Vec<Vec<AgentId>>,
+    /// `(sub, sup)` pairs of class indices, where nothing lies between `sub`
+    /// and `sup`.
+    pub edges: Vec<(usize, usize)>,
+    /// The class above all others, if there is one.
+    pub top: Vec<usize>,
+    /// The class below all others, if there is one.
+    pub bottom: Vec<usize>,
+}
+
+impl<'i> ProgramBuilder<'i> {
+    /// The agents used as types: the types of annotated agents, the agents
+    /// annotated as `Type`, and their cotypes.
+    pub fn type_list(&self) -> BTreeSet<AgentId> {
+        let instances = self.instances_by_type();
+        let kind = self.get_agent_id("Type");
+        let mut types = BTreeSet::new();
+        for (ty, instances) in &instances {
+            if Some(*ty) == kind {
+                types.extend(instances.iter().map(|(x, _)| *x));
+            } else {
+                types.insert(*ty);
+            }
+        }
+        let cotypes: Vec<_> = types.iter().map(|x| self.agent_inverse(*x)).collect();
+        types.extend(cotypes);
+        types
+    }
+    pub fn subtyping_lattice(&self) -> Lattice {
+        let system = self.interaction_system.as_ref().unwrap().clone();
+        let agents: Vec<_> = self.agent_list().collect();
+        // `a <= b` exactly when everything that interacts with `b` also
+        // interacts with `a`, so types with the same partners are equivalent.
+        let mut by_partners: BTreeMap<BTreeSet<AgentId>, Vec<AgentId>> = BTreeMap::new();
+        for ty in self.type_list() {
+            let partners = agents
+                .iter()
+                .copied()
+                .filter(|a| system.has_rule(*a, ty))
+                .collect();
+            by_partners.entry(partners).or_default().push(ty);
+        }
+        let (partners, classes): (Vec<_>, Vec<_>) = by_partners.into_iter().unzip();
+        let n = classes.len();
+        let below = |a: usize, b: usize| a != b && partners[b].is_subset(&partners[a]);
+        let mut edges = vec![];
+        for a in 0..n {
+            for b in 0..n {
+                if below(a, b) && !(0..n).any(|c| below(a, c) && below(c, b)) {
+                    edges.push((a, b));
+                }
+            }
+        }
+        // `Top` and `Bot` are the greatest and least classes, if there are any.
+        let top = (0..n)
+            .filter(|a| (0..n).all(|b| b == *a || below(b, *a)))
+            .collect();
+        let bottom = (0..n)
+            .filter(|a| (0..n).all(|b| b == *a || below(*a, b)))
+            .collect();
+        Lattice {
+            classes,
+            edges,
+            top,
+            bottom,
+        }
+    }
+    pub fn show_type_class(&self, class: &[AgentId]) -> String {
+        let names: Vec<_> = class.iter().map(|x| self.show_agent(*x)).collect();
+        names.join(" = ")
+    }
+    /// Renders the Hasse diagram of a lattice in Graphviz's DOT language,
+    /// with supertypes above their subtypes.
+    pub fn lattice_to_dot(&self, lattice: &Lattice) -> String {
+        let mut s = String::from("digraph subtyping {\n    rankdir=BT;\n    node [shape=box];\n");
+        for (i, class) in lattice.classes.iter().enumerate() {
+            let label = self.show_type_class(class).replace('"', "\\\"");
+            let style = if lattice.top.contains(&i) {
+                ", style=filled, fillcolor=lightblue"
+            } else if lattice.bottom.contains(&i) {
+                ", style=filled, fillcolor=lightpink"
+            } else {
+                ""
+            };
+            s += &format!("    t{} [label=\"{}\"{}];\n", i, label, style);
+        }
+        for (sub, sup) in &lattice.edges {
+            s += &format!("    t{} -> t{};\n", sub, sup);
+        }
+        s + "}"
+    }
+}
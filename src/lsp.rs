@@ -0,0 +1,455 @@
+//! A language server for `.itt` files, speaking LSP over stdin/stdout.
+//!
+//! Supports diagnostics (published on open and save), hover, go-to-definition
+//! and document symbols.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, BufRead, Write},
+    ops::Range,
+    panic::{AssertUnwindSafe, catch_unwind},
+};
+
+use crate::{
+    builder::{BUILTIN_AGENTS, ProgramBuilder},
+    json::Json,
+    report::{Diagnostic, Severity},
+    run::AgentId,
+};
+
+const SYMBOL_KIND_CONSTRUCTOR: u64 = 9;
+const SYMBOL_KIND_FUNCTION: u64 = 12;
+
+/// Parses `text` without running its `Reduce` blocks, then calls `f` with the
+/// built program and the parse error, if any.
+///
+/// Returns `None` if anything panicked along the way.
+fn with_program<T>(
+    text: &str,
+    f: impl FnOnce(&mut ProgramBuilder, Option<Diagnostic>) -> T,
+) -> Option<T> {
+    catch_unwind(AssertUnwindSafe(|| {
+        let mut p = ProgramBuilder::new(text);
+        let error = p.build().err().map(|e| {
+            let index = p.index;
+            Diagnostic::new("parse", e).at(Some(index..index + 1))
+        });
+        p.build_interaction_system();
+        f(&mut p, error)
+    }))
+    .ok()
+}
+
+/// Runs the parser and all the checkers on `text`.
+pub fn analyze(text: &str) -> Vec<Diagnostic> {
+    with_program(text, |p, error| match error {
+        Some(e) => vec![e],
+        None => [
+            p.check_completeness(),
+            p.check_inverse(),
+            p.check_derived(),
+            p.check_impls(),
+            p.check_subtypes(),
+            p.check_well_typedness(&BTreeSet::new()),
+            p.check_top_level(),
+            p.check_blocks(),
+            p.check_coverage(),
+        ]
+        .concat(),
+    })
+    .unwrap_or_else(|| {
+        vec![Diagnostic::new(
+            "internal",
+            "The checker crashed while analyzing this file".to_string(),
+        )]
+    })
+}
+
+fn strip_ansi(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn floor_char_boundary(text: &str, mut offset: usize) -> usize {
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+fn offset_to_position(text: &str, offset: usize) -> Json {
+    let offset = offset.min(text.len());
+    let before = &text[..floor_char_boundary(text, offset)];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map(|x| x + 1).unwrap_or(0);
+    let character: usize = before[line_start..].chars().map(|c| c.len_utf16()).sum();
+    Json::object([
+        ("line", Json::from(line)),
+        ("character", Json::from(character)),
+    ])
+}
+
+fn position_to_offset(text: &str, position: &Json) -> usize {
+    let line = position.get("line").and_then(Json::as_u64).unwrap_or(0) as usize;
+    let character = position
+        .get("character")
+        .and_then(Json::as_u64)
+        .unwrap_or(0) as usize;
+    let line_start = if line == 0 {
+        0
+    } else {
+        match text.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len(),
+        }
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn range(text: &str, span: &Range<usize>) -> Json {
+    Json::object([
+        ("start", offset_to_position(text, span.start)),
+        ("end", offset_to_position(text, span.end)),
+    ])
+}
+
+fn location(uri: &str, text: &str, span: &Range<usize>) -> Json {
+    Json::object([("uri", Json::from(uri)), ("range", range(text, span))])
+}
+
+/// Returns the agent name under `offset`, including a leading `~`.
+fn name_at(text: &str, offset: usize) -> Option<(String, Range<usize>)> {
+    let is_name = |c: char| ProgramBuilder::is_name_character(c) || c == '~';
+    let offset = floor_char_boundary(text, offset.min(text.len()));
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_name(*c))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(offset);
+    let end = text[offset..]
+        .char_indices()
+        .find(|(_, c)| !is_name(*c))
+        .map(|(i, _)| offset + i)
+        .unwrap_or(text.len());
+    let name = &text[start..end];
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_lowercase()) {
+        None
+    } else {
+        Some((name.to_string(), start..end))
+    }
+}
+
+fn publish_diagnostics(uri: &str, text: &str) -> Json {
+    let diagnostics = analyze(text)
+        .into_iter()
+        .map(|d| {
+            Json::object([
+                ("range", range(text, &d.span.clone().unwrap_or(0..0))),
+                (
+                    "severity",
+                    Json::from(match d.severity {
+                        Severity::Error => 1u64,
+                        Severity::Warning => 2,
+                    }),
+                ),
+                ("source", Json::from("inet-types")),
+                ("code", Json::from(d.check)),
+                ("message", Json::from(strip_ansi(&d.message))),
+            ])
+        })
+        .collect();
+    Json::object([
+        ("uri", Json::from(uri)),
+        ("diagnostics", Json::Array(diagnostics)),
+    ])
+}
+
+fn hover(text: &str, offset: usize) -> Json {
+    let Some((name, span)) = name_at(text, offset) else {
+        return Json::Null;
+    };
+    with_program(text, |p, _| {
+        let agent = p.lookup_agent(&name)?;
+        let arity = p
+            .arities
+            .get(&agent)
+            .map(|x| x.to_string())
+            .unwrap_or("unknown".to_string());
+        let ty = p
+            .get_signature(agent)
+            .map(|x| format!("`{}`", p.show_signature(&x)))
+            .unwrap_or("unknown".to_string());
+        Some(Json::object([
+            (
+                "contents",
+                Json::object([
+                    ("kind", Json::from("markdown")),
+                    (
+                        "value",
+                        Json::from(format!(
+                            "```\n{}\n```\narity: {}\n\ntype: {}",
+                            name, arity, ty
+                        )),
+                    ),
+                ]),
+            ),
+            ("range", range(text, &span)),
+        ]))
+    })
+    .flatten()
+    .unwrap_or(Json::Null)
+}
+
+fn definition(uri: &str, text: &str, offset: usize) -> Json {
+    with_program(text, |p, _| {
+        let rules_for = |pred: &dyn Fn(AgentId, AgentId) -> bool| -> Vec<Json> {
+            p.def
+                .iter()
+                .filter(|x| pred(x.left_id, x.right_id))
+                .map(|x| location(uri, text, &x.span))
+                .collect()
+        };
+        // Inside an active pair, jump to the rule that reduces it.
+        for ((a, b), span) in &p.redex_spans {
+            if span.contains(&offset) {
+                let (a, b) = (*a, *b);
+                let rules = rules_for(&|l, r| (l, r) == (a, b) || (l, r) == (b, a));
+                if !rules.is_empty() {
+                    return rules;
+                }
+            }
+        }
+        // Otherwise, list the rules of the agent under the cursor.
+        let Some(agent) = name_at(text, offset).and_then(|(name, _)| p.lookup_agent(&name)) else {
+            return vec![];
+        };
+        rules_for(&|l, r| l == agent || r == agent)
+    })
+    .map(Json::Array)
+    .unwrap_or(Json::Null)
+}
+
+fn document_symbols(uri: &str, text: &str) -> Json {
+    with_program(text, |p, _| {
+        let symbol = |name: String, kind: u64, span: &Range<usize>| {
+            Json::object([
+                ("name", Json::from(name)),
+                ("kind", Json::from(kind)),
+                ("location", location(uri, text, span)),
+            ])
+        };
+        let agents = p
+            .agent_spans
+            .iter()
+            .filter(|(name, _)| !BUILTIN_AGENTS.contains(&name.as_str()))
+            .map(|(name, span)| symbol(name.clone(), SYMBOL_KIND_CONSTRUCTOR, span));
+        let rules = p.def.iter().map(|x| {
+            symbol(
+                format!("{} ~ {}", p.show_agent(x.left_id), p.show_agent(x.right_id)),
+                SYMBOL_KIND_FUNCTION,
+                &x.span,
+            )
+        });
+        agents.chain(rules).collect()
+    })
+    .map(Json::Array)
+    .unwrap_or(Json::Null)
+}
+
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':')
+            && key.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Missing Content-Length header",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8_lossy(&body);
+    Json::parse(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(output: &mut impl Write, message: Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn response(id: Json, result: Json) -> Json {
+    Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("id", id),
+        ("result", result),
+    ])
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from(method)),
+        ("params", params),
+    ])
+}
+
+fn capabilities() -> Json {
+    Json::object([(
+        "capabilities",
+        Json::object([
+            (
+                "textDocumentSync",
+                Json::object([
+                    ("openClose", Json::from(true)),
+                    // Full document sync
+                    ("change", Json::from(1u64)),
+                    ("save", Json::object([("includeText", Json::from(true))])),
+                ]),
+            ),
+            ("hoverProvider", Json::from(true)),
+            ("definitionProvider", Json::from(true)),
+            ("documentSymbolProvider", Json::from(true)),
+        ]),
+    )])
+}
+
+/// Serves requests until the client sends `exit` or closes stdin.
+pub fn serve() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut output = io::stdout();
+    let mut documents: BTreeMap<String, String> = BTreeMap::new();
+
+    while let Some(message) = read_message(&mut input)? {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        let uri = params
+            .get("textDocument")
+            .and_then(|x| x.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string();
+        let offset = |documents: &BTreeMap<String, String>| {
+            let text = documents.get(&uri).map(|x| x.as_str()).unwrap_or("");
+            position_to_offset(text, params.get("position").unwrap_or(&Json::Null))
+        };
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => Json::Null,
+            "exit" => return Ok(()),
+            "textDocument/didOpen" | "textDocument/didSave" | "textDocument/didChange" => {
+                let text = match method {
+                    "textDocument/didChange" => params
+                        .get("contentChanges")
+                        .and_then(|x| match x {
+                            Json::Array(changes) => changes.last(),
+                            _ => None,
+                        })
+                        .and_then(|x| x.get("text")),
+                    "textDocument/didSave" => params.get("text"),
+                    _ => params.get("textDocument").and_then(|x| x.get("text")),
+                };
+                if let Some(text) = text.and_then(Json::as_str) {
+                    documents.insert(uri.clone(), text.to_string());
+                }
+                if method != "textDocument/didChange"
+                    && let Some(text) = documents.get(&uri)
+                {
+                    write_message(
+                        &mut output,
+                        notification(
+                            "textDocument/publishDiagnostics",
+                            publish_diagnostics(&uri, text),
+                        ),
+                    )?;
+                }
+                continue;
+            }
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+                continue;
+            }
+            "textDocument/hover" => {
+                let offset = offset(&documents);
+                hover(
+                    documents.get(&uri).map(|x| x.as_str()).unwrap_or(""),
+                    offset,
+                )
+            }
+            "textDocument/definition" => {
+                let offset = offset(&documents);
+                definition(
+                    &uri,
+                    documents.get(&uri).map(|x| x.as_str()).unwrap_or(""),
+                    offset,
+                )
+            }
+            "textDocument/documentSymbol" => {
+                document_symbols(&uri, documents.get(&uri).map(|x| x.as_str()).unwrap_or(""))
+            }
+            _ => {
+                if let Some(id) = message.get("id") {
+                    write_message(
+                        &mut output,
+                        Json::object([
+                            ("jsonrpc", Json::from("2.0")),
+                            ("id", id.clone()),
+                            (
+                                "error",
+                                Json::object([
+                                    ("code", Json::Number(-32601.0)),
+                                    (
+                                        "message",
+                                        Json::from(format!("Unknown method `{}`", method)),
+                                    ),
+                                ]),
+                            ),
+                        ]),
+                    )?;
+                }
+                continue;
+            }
+        };
+        if let Some(id) = message.get("id") {
+            write_message(&mut output, response(id.clone(), result))?;
+        }
+    }
+    Ok(())
+}
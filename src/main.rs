@@ -1,16 +1,30 @@
-#![feature(let_chains, is_none_or)]
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    rc::Rc,
+};
 
-use std::{collections::BTreeMap, rc::Rc};
-
-use builder::{Definition, ProgramBuilder, Vars};
 use TSPL::Parser;
+use builder::{BUILTIN_AGENTS, Definition, ProgramBuilder, Vars};
 
 pub mod builder;
 pub mod checker;
 pub mod compiler;
+pub mod data;
+pub mod infer;
+pub mod json;
+pub mod lattice;
+pub mod lsp;
+pub mod reduce;
+pub mod repl;
+pub mod report;
 pub mod run;
+pub mod signature;
+pub mod stubs;
+pub mod subtypes;
 pub mod syntax;
+pub mod traits;
 
+use report::{Diagnostic, Format};
 use run::{InteractionSystem, Tree as RtTree, VarId};
 use syntax::Tree;
 
@@ -21,8 +35,9 @@ impl<'i> ProgramBuilder<'i> {
                 let id = self.get_or_new_agent_id(name);
                 self.set_arity(id, aux.len() as u64);
                 RtTree::Agent {
-                    id: id,
+                    id,
                     aux: aux.into_iter().map(|x| self.tree_ast_to_rt(x)).collect(),
+                    origin: None,
                 }
             }
             Tree::Var(id) => RtTree::Var {
@@ -73,6 +88,7 @@ impl<'i> ProgramBuilder<'i> {
                 self.parse_macro()?;
             } else {
                 // Parse a tree
+                let start = self.index;
                 let left = self.parse_tree()?;
                 self.skip_trivia();
                 match self.peek_one() {
@@ -80,6 +96,13 @@ impl<'i> ProgramBuilder<'i> {
                         self.consume("=")?;
                         let right = self.parse_tree()?;
 
+                        if let (Tree::Agent(l_name, _), Tree::Agent(r_name, _)) = (&left, &right) {
+                            let pair = (
+                                self.get_or_new_agent_id(l_name.clone()),
+                                self.get_or_new_agent_id(r_name.clone()),
+                            );
+                            self.redex_spans.push((pair, self.span_from(start)));
+                        }
                         let interaction = (self.tree_ast_to_rt(left), self.tree_ast_to_rt(right));
                         self.levels
                             .last_mut()
@@ -92,52 +115,15 @@ impl<'i> ProgramBuilder<'i> {
                     Some('~') => {
                         self.consume("~")?;
                         let right = self.parse_tree()?;
-
-                        self.enter();
-
-                        let Tree::Agent(l_name, l_children) = left else {
-                            return Err("Invalid item found!".to_string());
-                        };
-                        let Tree::Agent(r_name, r_children) = right else {
-                            return Err("Invalid item found!".to_string());
-                        };
-                        let l_children: Vec<_> = l_children
-                            .into_iter()
-                            .map(|x| self.tree_ast_to_rt(x))
-                            .collect();
-                        let r_children: Vec<_> = r_children
-                            .into_iter()
-                            .map(|x| self.tree_ast_to_rt(x))
-                            .collect();
-                        self.maybe_parse_scope()?;
-                        let mut scope = self.exit().unwrap();
-                        self.levels
-                            .last_mut()
-                            .unwrap()
-                            .agent_scope
-                            .extend(scope.agent_scope);
-
-                        scope.vars.net.normal();
-                        let l_children = l_children
-                            .into_iter()
-                            .map(|x| scope.vars.net.substitute(x))
-                            .collect();
-                        let r_children = r_children
-                            .into_iter()
-                            .map(|x| scope.vars.net.substitute(x))
-                            .collect();
-
-                        let def = Definition {
-                            left_id: self.get_or_new_agent_id(l_name),
-                            left_children: l_children,
-                            right_id: self.get_or_new_agent_id(r_name),
-                            right_children: r_children,
-                        };
-                        self.def.push(def);
+                        self.define_rule(left, right, start, true)?;
+                    }
+                    Some(':') => {
+                        self.consume(":")?;
+                        let (left, right) = self.parse_signature(left)?;
+                        self.define_rule(left, right, start, false)?;
                     }
                     _ => {
-                        // :(
-                        todo!();
+                        return Err("Expected `=`, `~` or `:` after a tree.".to_string());
                     }
                 }
             }
@@ -145,6 +131,64 @@ impl<'i> ProgramBuilder<'i> {
         }
         Ok(())
     }
+    /// Defines the rule `left ~ right`, whose body (if `body` is set and
+    /// there is one) follows in the input.
+    fn define_rule(
+        &mut self,
+        left: Tree,
+        right: Tree,
+        start: usize,
+        body: bool,
+    ) -> Result<(), String> {
+        self.enter();
+        let Tree::Agent(l_name, l_children) = left else {
+            return Err("Invalid item found!".to_string());
+        };
+        let Tree::Agent(r_name, r_children) = right else {
+            return Err("Invalid item found!".to_string());
+        };
+        let l_children: Vec<_> = l_children
+            .into_iter()
+            .map(|x| self.tree_ast_to_rt(x))
+            .collect();
+        let r_children: Vec<_> = r_children
+            .into_iter()
+            .map(|x| self.tree_ast_to_rt(x))
+            .collect();
+        if body {
+            self.maybe_parse_scope()?;
+        }
+        let mut scope = self.exit().unwrap();
+        self.levels
+            .last_mut()
+            .unwrap()
+            .agent_scope
+            .extend(scope.agent_scope);
+
+        scope.vars.net.normal();
+        let l_children: Vec<_> = l_children
+            .into_iter()
+            .map(|x| scope.vars.net.substitute(x))
+            .collect();
+        let r_children: Vec<_> = r_children
+            .into_iter()
+            .map(|x| scope.vars.net.substitute(x))
+            .collect();
+
+        let left_id = self.get_or_new_agent_id(l_name);
+        self.set_arity(left_id, l_children.len() as u64);
+        let right_id = self.get_or_new_agent_id(r_name);
+        self.set_arity(right_id, r_children.len() as u64);
+        let def = Definition {
+            left_id,
+            left_children: l_children,
+            right_id,
+            right_children: r_children,
+            span: self.span_from(start),
+        };
+        self.def.push(def);
+        Ok(())
+    }
     fn build(&mut self) -> Result<(), String> {
         let a = self.get_or_new_agent_id(":".into());
         self.set_arity(a, 2);
@@ -152,104 +196,344 @@ impl<'i> ProgramBuilder<'i> {
         self.set_arity(a, 1);
         let a = self.get_or_new_agent_id("::".into());
         self.set_arity(a, 1);
+        let a = self.get_or_new_agent_id("&".into());
+        self.set_arity(a, 2);
         self.parse_scope()?;
         Ok(())
     }
     fn build_interaction_system(&mut self) -> Rc<InteractionSystem> {
-        let antitype_agent = self.get_or_new_agent_id("~".to_string());
-        let annotator_agent = self.get_or_new_agent_id("::".to_string());
+        let isys = self.interaction_system_with(self.def.clone());
+        self.interaction_system = Some(isys.clone());
+        isys
+    }
+    /// Builds an interaction system that only knows the rules in `def`.
+    fn interaction_system_with(&self, def: Vec<Definition>) -> Rc<InteractionSystem> {
+        let antitype_agent = self.get_agent_id("~").unwrap();
+        let copier_agent = self.get_agent_id("&").unwrap();
+        let special: Vec<_> = BUILTIN_AGENTS
+            .into_iter()
+            .filter_map(|x| self.get_agent_id(x))
+            .map(|x| x.0)
+            .collect();
         let arities = self.arities.clone();
-        let def = self.def.clone();
-        let _agent_scope_back = self.agent_scope_back.clone();
-        let isys = Rc::new(InteractionSystem {
+        // Rule for each ordered pair of agents, and whether it was written the
+        // other way around. The first definition of a pair wins.
+        let mut table = BTreeMap::new();
+        for (i, definition) in def.iter().enumerate() {
+            table
+                .entry((definition.left_id, definition.right_id))
+                .or_insert((i, false));
+            table
+                .entry((definition.right_id, definition.left_id))
+                .or_insert((i, true));
+        }
+        Rc::new(InteractionSystem {
             get: Box::new(move |a, b| {
-                for definition in &def {
-                    //println!("{:?}", (agent_scope_back.get(&definition.left_id), agent_scope_back.get(&definition.right_id)));
-                    //println!("{:?}", (agent_scope_back.get(&a), agent_scope_back.get(&b)));
-                    //println!("\n    {:?}\n    {:?}\n    {:?}", a, b, definition);
-                    if *a == definition.left_id && *b == definition.right_id {
-                        //println!("Yay!");
-                        return Some(run::InteractionRule {
-                            left_ports: definition.left_children.clone(),
-                            right_ports: definition.right_children.clone(),
-                        });
-                    }
-                    if *b == definition.left_id && *a == definition.right_id {
-                        //println!("Yay!");
-                        return Some(run::InteractionRule {
-                            left_ports: definition.right_children.clone(),
-                            right_ports: definition.left_children.clone(),
-                        });
-                    }
+                if let Some(&(i, flipped)) = table.get(&(*a, *b)) {
+                    let definition = &def[i];
+                    let (left_ports, right_ports) = if flipped {
+                        (&definition.right_children, &definition.left_children)
+                    } else {
+                        (&definition.left_children, &definition.right_children)
+                    };
+                    return Some(run::InteractionRule {
+                        left_ports: left_ports.clone(),
+                        right_ports: right_ports.clone(),
+                        rule: Some(i),
+                    });
                 }
                 if *a == antitype_agent && *b == antitype_agent {
                     return Some(run::InteractionRule {
                         left_ports: vec![RtTree::Var { id: VarId(0) }],
                         right_ports: vec![RtTree::Var { id: VarId(0) }],
+                        rule: None,
+                    });
+                }
+                if *a == antitype_agent && *b == copier_agent {
+                    // The dual of a copy is a copy of the dual: ~(&(x y)) = &(~(x) ~(y)).
+                    return Some(run::InteractionRule {
+                        left_ports: vec![RtTree::Agent {
+                            id: copier_agent,
+                            aux: (0..2)
+                                .map(|x| RtTree::Agent {
+                                    id: antitype_agent,
+                                    aux: vec![RtTree::Var { id: VarId(x) }],
+                                    origin: None,
+                                })
+                                .collect(),
+                            origin: None,
+                        }],
+                        right_ports: (0..2).map(|x| RtTree::Var { id: VarId(x) }).collect(),
+                        rule: None,
                     });
                 }
                 if *a == antitype_agent {
+                    let arity = *arities.get(b)?;
                     return Some(run::InteractionRule {
                         left_ports: vec![RtTree::Agent {
                             id: crate::run::AgentId(b.0, (b.1 + 1) % 2),
-                            aux: (0..arities[b])
+                            aux: (0..arity)
                                 .map(|x| RtTree::Agent {
                                     id: antitype_agent,
                                     aux: vec![RtTree::Var { id: VarId(x) }],
+                                    origin: None,
                                 })
                                 .collect(),
+                            origin: None,
                         }],
-                        right_ports: (0..arities[b])
-                            .map(|x| RtTree::Var { id: VarId(x) })
-                            .collect(),
+                        right_ports: (0..arity).map(|x| RtTree::Var { id: VarId(x) }).collect(),
+                        rule: None,
                     });
                 }
                 if a.0 == b.0 && a.1 == (b.1 + 1) % 2 {
                     // A ~ ~A
                     // Interaction with the inverse
+                    let arity = *arities.get(a)?;
                     return Some(run::InteractionRule {
-                        left_ports: (0..arities[a])
-                            .map(|x| RtTree::Var { id: VarId(x) })
-                            .collect(),
-                        right_ports: (0..arities[a])
-                            .map(|x| RtTree::Var { id: VarId(x) })
-                            .collect(),
+                        left_ports: (0..arity).map(|x| RtTree::Var { id: VarId(x) }).collect(),
+                        right_ports: (0..arity).map(|x| RtTree::Var { id: VarId(x) }).collect(),
+                        rule: None,
                     });
                 }
-                /*
-                if *a == annotator_agent {
+                if *a == copier_agent && !special.contains(&b.0) {
+                    // &(x y) ~ B(a0 ...) copies B: x = B(c0 ...), y = B(d0 ...),
+                    // and a0 = &(c0 d0) ...
+                    let arity = *arities.get(b)?;
+                    let copy = |offset| RtTree::Agent {
+                        id: *b,
+                        aux: (0..arity)
+                            .map(|x| RtTree::Var {
+                                id: VarId(offset + x),
+                            })
+                            .collect(),
+                        origin: None,
+                    };
                     return Some(run::InteractionRule {
-                        left_ports: (0..arities[a])
+                        left_ports: vec![copy(0), copy(arity)],
+                        right_ports: (0..arity)
                             .map(|x| RtTree::Agent {
-                                id: annotator_agent,
-                                aux: vec![RtTree::Var { id: VarId(x) }],
+                                id: copier_agent,
+                                aux: vec![
+                                    RtTree::Var { id: VarId(x) },
+                                    RtTree::Var {
+                                        id: VarId(arity + x),
+                                    },
+                                ],
+                                origin: None,
                             })
                             .collect(),
-                        right_ports: vec![RtTree::Agent {
-                            id: annotator_agent,
-                            aux: (0..arities[a])
-                                .map(|x| RtTree::Var { id: VarId(x) })
-                                .collect(),
-                        }],
+                        rule: None,
                     });
-                }*/
-                return None;
+                }
+                None
             }),
-        });
-        self.interaction_system = Some(isys.clone());
-        isys
+        })
+    }
+}
+
+enum Command {
+    Check(String),
+    Repl(String),
+    Lsp,
+}
+
+struct Options {
+    command: Command,
+    format: Format,
+    rules_so_far: bool,
+    trace_rules: bool,
+    coverage: bool,
+    strict_typing: bool,
+    exhaustiveness: bool,
+    stubs: bool,
+    stubs_file: Option<String>,
+    lattice: bool,
+    lattice_dot: bool,
+    show_desugared: bool,
+    infer: bool,
+}
+
+const USAGE: &str = "Usage: inet-types [--format text|json] [--rules-so-far] [--trace-rules]
+                  [--coverage] [--strict-typing] [--exhaustiveness]
+                  [--stubs] [--stubs-file <path>] [--lattice] [--lattice-dot]
+                  [--show-desugared] [--infer] <file>
+       inet-types repl <file>
+       inet-types lsp";
+
+fn parse_args() -> Result<Options, String> {
+    let mut positional = vec![];
+    let mut format = Format::Text;
+    let mut rules_so_far = false;
+    let mut trace_rules = false;
+    let mut coverage = false;
+    let mut strict_typing = false;
+    let mut exhaustiveness = false;
+    let mut stubs = false;
+    let mut stubs_file = None;
+    let mut lattice = false;
+    let mut lattice_dot = false;
+    let mut show_desugared = false;
+    let mut infer = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = Format::parse(&args.next().ok_or("Expected a format after --format")?)?
+            }
+            "--rules-so-far" => rules_so_far = true,
+            "--trace-rules" => trace_rules = true,
+            "--coverage" => coverage = true,
+            "--strict-typing" => strict_typing = true,
+            "--exhaustiveness" => exhaustiveness = true,
+            "--stubs" => stubs = true,
+            "--lattice" => lattice = true,
+            "--lattice-dot" => lattice_dot = true,
+            "--show-desugared" => show_desugared = true,
+            "--infer" => infer = true,
+            "--stubs-file" => {
+                stubs_file = Some(args.next().ok_or("Expected a path after --stubs-file")?)
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option `{}`", arg)),
+            _ => positional.push(arg),
+        }
     }
+    let command = match positional.as_slice() {
+        [lsp] if lsp == "lsp" => Command::Lsp,
+        [repl, file] if repl == "repl" => Command::Repl(file.clone()),
+        [file] => Command::Check(file.clone()),
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok(Options {
+        command,
+        format,
+        rules_so_far,
+        trace_rules,
+        coverage,
+        strict_typing,
+        exhaustiveness,
+        stubs,
+        stubs_file,
+        lattice,
+        lattice_dot,
+        show_desugared,
+        infer,
+    })
 }
 
 fn main() {
-    let s = std::fs::read_to_string(std::env::args().skip(1).next().unwrap()).unwrap();
+    let options = match parse_args() {
+        Ok(o) => o,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let file = match options.command {
+        Command::Check(file) => file,
+        Command::Repl(file) => {
+            if let Err(e) = repl::run(&file) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Command::Lsp => {
+            if let Err(e) = lsp::serve() {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+    };
+    let s = std::fs::read_to_string(&file).unwrap();
     let mut p = ProgramBuilder::new(&s);
+    p.format = options.format;
+    p.rules_so_far = options.rules_so_far;
+    p.trace_all_rules = options.trace_rules;
+    p.strict_typing = options.strict_typing;
     match p.build() {
         Ok(o) => o,
-        Err(e) => println!("{}", e),
+        Err(e) => {
+            let index = p.index;
+            p.emit(&Diagnostic::new("parse", e).at(Some(index..index + 1)))
+        }
     };
+    if options.show_desugared {
+        for (source, rule) in &p.desugared {
+            p.emit_desugared(source, rule);
+        }
+    }
     p.build_interaction_system();
-    p.check_completeness();
-    p.check_inverse();
-    p.check_well_typedness();
+    // Agents whose type couldn't be inferred, whose rules aren't checked again.
+    let mut uninferred = BTreeSet::new();
+    if options.infer {
+        let (inferred, diagnostics) = p.infer_annotations();
+        for inferred in inferred {
+            p.emit_inferred(&inferred);
+            p.def.push(inferred.rule);
+        }
+        for (agent, diagnostic) in &diagnostics {
+            p.emit(diagnostic);
+            uninferred.insert(*agent);
+        }
+        p.build_interaction_system();
+    }
+    p.run_pending();
+    p.trace_rules();
+    let diagnostics = [
+        p.check_completeness(),
+        p.check_inverse(),
+        p.check_derived(),
+        p.check_impls(),
+        p.check_subtypes(),
+        p.check_well_typedness(&uninferred),
+        p.check_top_level(),
+        if options.coverage || options.strict_typing {
+            p.check_coverage()
+        } else {
+            vec![]
+        },
+    ];
+    for diagnostic in diagnostics.iter().flatten() {
+        p.emit(diagnostic);
+    }
+    if options.exhaustiveness {
+        for matrix in p.exhaustiveness() {
+            p.emit_exhaustiveness(&matrix);
+        }
+    }
+    if options.lattice || options.lattice_dot {
+        let lattice = p.subtyping_lattice();
+        if options.lattice {
+            p.emit_lattice(&lattice);
+        }
+        if options.lattice_dot {
+            p.emit_lattice_dot(&lattice);
+        }
+    }
+    if options.stubs || options.stubs_file.is_some() {
+        let stubs = p.stub_rules();
+        if options.stubs {
+            for stub in &stubs {
+                p.emit_stub(stub);
+            }
+        }
+        if let Some(path) = options.stubs_file
+            && !stubs.is_empty()
+        {
+            let contents: String = stubs.iter().map(|x| format!("\n{}\n", x)).collect();
+            let result = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut f| std::io::Write::write_all(&mut f, contents.as_bytes()));
+            if let Err(e) = result {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if p.errors.get() > 0 {
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,252 @@
+//! Execution of `Reduce` and `Check` blocks.
+//!
+//! Blocks are collected while parsing and only run once the whole file has
+//! been read, so that every block sees every rule.
+
+use std::{collections::BTreeMap, rc::Rc};
+
+use crate::{
+    builder::{BlockKind, Level, PendingBlock, ProgramBuilder},
+    report::{Diagnostic, Severity},
+    run::{InteractionSystem, Net, ReduceObserver, Strategy, Tree, VarId},
+};
+
+/// What a `Reduce` block prints.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Trace {
+    /// The net after every interaction.
+    #[default]
+    Steps,
+    /// Only the normal form.
+    Final,
+    /// Only counts of what happened.
+    Stats,
+}
+
+/// Options given inside `Reduce[...]`, before the net.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReduceOptions {
+    pub trace: Trace,
+    pub strategy: Strategy,
+    /// Maximum number of interactions to perform.
+    pub fuel: Option<u64>,
+    /// Whether to run the block even if its net is not well typed.
+    pub unchecked: bool,
+}
+
+impl ReduceOptions {
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid = |expected: &str| {
+            Err(format!(
+                "Invalid value `{}` for Reduce option `{}`; expected {}",
+                value, key, expected
+            ))
+        };
+        if value.is_empty() && key != "unchecked" {
+            return Err(format!("Reduce option `{}` needs a value", key));
+        }
+        match key {
+            "trace" => {
+                self.trace = match value {
+                    "steps" => Trace::Steps,
+                    "final" => Trace::Final,
+                    "stats" => Trace::Stats,
+                    _ => return invalid("`steps`, `final` or `stats`"),
+                }
+            }
+            "strategy" => {
+                self.strategy = match value {
+                    "lifo" => Strategy::Lifo,
+                    "fifo" => Strategy::Fifo,
+                    _ => return invalid("`lifo` or `fifo`"),
+                }
+            }
+            "fuel" => match value.parse() {
+                Ok(fuel) => self.fuel = Some(fuel),
+                Err(_) => return invalid("a number of interactions"),
+            },
+            "unchecked" => {
+                self.unchecked = match value {
+                    "" | "true" => true,
+                    "false" => false,
+                    _ => return invalid("`true` or `false`"),
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown Reduce option `{}`; expected `trace`, `strategy`, `fuel` or `unchecked`",
+                    key
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints a reduction trace and keeps counts for `trace=stats`.
+pub struct TraceObserver<'a, 'i> {
+    builder: &'a ProgramBuilder<'i>,
+    vars: &'a mut BTreeMap<VarId, String>,
+    trace: Trace,
+    step: usize,
+    redex: (String, String),
+    interactions: usize,
+    links: usize,
+}
+
+impl<'a, 'i> TraceObserver<'a, 'i> {
+    pub fn new(
+        builder: &'a ProgramBuilder<'i>,
+        vars: &'a mut BTreeMap<VarId, String>,
+        trace: Trace,
+    ) -> Self {
+        Self {
+            builder,
+            vars,
+            trace,
+            step: 0,
+            redex: Default::default(),
+            interactions: 0,
+            links: 0,
+        }
+    }
+}
+
+impl<'a, 'i> ReduceObserver for TraceObserver<'a, 'i> {
+    fn before_interaction(&mut self, net: &Net, a: &Tree, b: &Tree) {
+        match (a, b) {
+            (Tree::Agent { .. }, Tree::Agent { .. }) => self.interactions += 1,
+            _ => self.links += 1,
+        }
+        if self.trace == Trace::Steps {
+            self.redex = (
+                self.builder.show_tree(net, self.vars, a),
+                self.builder.show_tree(net, self.vars, b),
+            );
+        }
+    }
+    fn after_interaction(&mut self, net: &Net) {
+        self.step += 1;
+        if self.trace == Trace::Steps {
+            let redex = std::mem::take(&mut self.redex);
+            self.builder.emit_step(self.step, redex, net, self.vars);
+        }
+    }
+}
+
+impl<'i> ProgramBuilder<'i> {
+    /// The rules a block gets to use.
+    fn block_system(&self, block: &PendingBlock) -> Rc<InteractionSystem> {
+        if self.rules_so_far {
+            self.interaction_system_with(self.def[..block.rules_before].to_vec())
+        } else {
+            self.interaction_system.clone().unwrap()
+        }
+    }
+    fn check_block(&self, block: &PendingBlock) -> Vec<Diagnostic> {
+        let mut net = block.level.vars.net.clone();
+        net.system = self.block_system(block);
+        let names = block
+            .level
+            .vars
+            .var_scope
+            .iter()
+            .map(|(k, v)| (*v, k.clone()))
+            .collect();
+        self.check_net(&net, &names)
+            .into_iter()
+            // There is nothing to check about agents without a type, so they
+            // only make a `Check` block fail, and `Reduce` runs them as they
+            // are.
+            .filter(|x| matches!(block.kind, BlockKind::Check) || x.check != "untyped")
+            .map(|x| x.at(Some(block.span.clone())))
+            .collect()
+    }
+    /// Type-checks the pending blocks without running anything.
+    pub fn check_blocks(&self) -> Vec<Diagnostic> {
+        self.pending
+            .iter()
+            .filter(|x| match x.kind {
+                BlockKind::Check => true,
+                BlockKind::Reduce(options) => !options.unchecked,
+            })
+            .flat_map(|x| self.check_block(x))
+            .collect()
+    }
+    /// Runs all the pending blocks, in source order.
+    pub fn run_pending(&mut self) {
+        if self.interaction_system.is_none() {
+            self.build_interaction_system();
+        }
+        for mut block in std::mem::take(&mut self.pending) {
+            match block.kind {
+                BlockKind::Check => {
+                    let diagnostics = self.check_block(&block);
+                    for diagnostic in &diagnostics {
+                        self.emit(diagnostic);
+                    }
+                    self.emit_check(diagnostics.iter().all(|x| x.severity == Severity::Warning));
+                }
+                BlockKind::Reduce(options) => {
+                    if !options.unchecked {
+                        let diagnostics = self.check_block(&block);
+                        for diagnostic in &diagnostics {
+                            self.emit(diagnostic);
+                        }
+                        if diagnostics.iter().any(|x| x.severity == Severity::Error) {
+                            self.emit(
+                                &Diagnostic::new(
+                                    "reduce",
+                                    "Not reducing a net that is not well typed; \
+                                    use `Reduce[unchecked { ... }]` to reduce it anyway"
+                                        .to_string(),
+                                )
+                                .at(Some(block.span.clone())),
+                            );
+                            continue;
+                        }
+                    }
+                    block.level.vars.net.system = self.block_system(&block);
+                    self.run_reduce(block.level, options);
+                }
+            }
+        }
+    }
+    fn run_reduce(&self, mut result: Level, options: ReduceOptions) {
+        let mut vars = result
+            .vars
+            .var_scope
+            .iter()
+            .map(|(k, v)| (*v, k.clone()))
+            .collect();
+        let net = &mut result.vars.net;
+        let mut observer = TraceObserver::new(self, &mut vars, options.trace);
+        let steps = net.reduce(options.strategy, options.fuel, &mut observer);
+        let (interactions, links) = (observer.interactions, observer.links);
+        match options.trace {
+            Trace::Steps => {}
+            Trace::Final => self.emit_normal_form(net, &mut vars),
+            Trace::Stats => self.emit_stats(&[
+                ("steps", steps as usize),
+                ("interactions", interactions),
+                ("links", links),
+                ("stuck", net.stuck.len()),
+            ]),
+        }
+        // Running out of fuel is how `fuel=n` stops a reduction, so it
+        // isn't an error.
+        if !net.interactions.is_empty() {
+            self.emit(
+                &Diagnostic::new(
+                    "fuel",
+                    format!(
+                        "Ran out of fuel after {} steps, with {} active pairs left",
+                        steps,
+                        net.interactions.len()
+                    ),
+                )
+                .severity(Severity::Warning),
+            );
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! An interactive loop for exploring an interaction system.
+//!
+//! Lines are either nets, which get added to the current net, or commands
+//! starting with `:`.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    builder::{Level, ProgramBuilder},
+    run::AgentId,
+};
+
+const HELP: &str = "\
+<net>          Add active pairs to the current net, e.g. `Bool.not(out) = Bool.true`
+:type A        Show the types of the ports of agent A
+:sub A B       Check whether A is a subtype of B
+:rules A       List the agents that A can interact with
+:step          Reduce one active pair of the current net
+:normal        Reduce the current net to normal form
+:show          Show the current net
+:clear         Discard the current net
+:reload        Reload the file and discard the current net
+:help          Show this message
+:quit          Exit";
+
+pub struct Repl {
+    path: String,
+    program: ProgramBuilder<'static>,
+    net: Level,
+}
+
+impl Repl {
+    fn load(path: &str) -> Result<ProgramBuilder<'static>, String> {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut p = ProgramBuilder::new(&source);
+        p.build()?;
+        p.build_interaction_system();
+        Ok(p.with_input(""))
+    }
+    pub fn new(path: &str) -> Result<Self, String> {
+        Ok(Repl {
+            path: path.to_string(),
+            program: Self::load(path)?,
+            net: Self::empty_net(),
+        })
+    }
+    fn empty_net() -> Level {
+        Level::default()
+    }
+    fn agent(&self, name: &str) -> Result<AgentId, String> {
+        self.program
+            .lookup_agent(name)
+            .ok_or(format!("Unknown agent `{}`", name))
+    }
+    fn show_net(&self) -> String {
+        let mut vars: BTreeMap<_, _> = self
+            .net
+            .vars
+            .var_scope
+            .iter()
+            .map(|(k, v)| (*v, k.clone()))
+            .collect();
+        self.net
+            .vars
+            .net
+            .show_net(&|id| self.program.show_agent(id), &mut vars, true)
+    }
+    fn add_net(&mut self, line: &str) -> Result<(), String> {
+        // Forget the names of variables that have been linked away, since
+        // their ids can be reused.
+        let vars = &mut self.net.vars;
+        vars.var_scope
+            .retain(|_, id| vars.net.vars.contains_key(id));
+        let program = std::mem::replace(&mut self.program, ProgramBuilder::new(""));
+        let mut p = program.with_input(line);
+        let definitions = p.def.len();
+        // Spans in the line don't point into the file, so they aren't kept.
+        let redexes = p.redex_spans.len();
+        let agent_spans = p.agent_spans.clone();
+        let depth = p.levels.len();
+        let previous = self.net.clone();
+        p.levels.push(std::mem::take(&mut self.net));
+        let result = p.parse_scope().and_then(|_| {
+            if p.index < line.len() {
+                Err(format!("Unexpected `{}`", &line[p.index..]))
+            } else if p.def.len() > definitions {
+                Err("Rules can't be defined here; add them to the file and use :reload".to_string())
+            } else {
+                Ok(())
+            }
+        });
+        p.def.truncate(definitions);
+        p.redex_spans.truncate(redexes);
+        p.agent_spans = agent_spans;
+        // A parse error can leave nested scopes behind.
+        p.levels.truncate(depth + 1);
+        self.net = if result.is_ok() {
+            let mut net = p.exit().unwrap();
+            // Agents first named at the prompt become known to the program,
+            // so that commands such as `:type` can find them.
+            let agents = std::mem::take(&mut net.agent_scope);
+            p.levels.last_mut().unwrap().agent_scope.extend(agents);
+            net
+        } else {
+            previous
+        };
+        // The net can name agents that the file doesn't, so the system has to
+        // know their arities.
+        self.net.vars.net.system = p.build_interaction_system();
+        self.program = p.with_input("");
+        result
+    }
+    /// Runs a single line of input. Returns `false` if the REPL should exit.
+    pub fn run_line(&mut self, line: &str) -> Result<bool, String> {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None => {}
+            Some(":quit" | ":q") => return Ok(false),
+            Some(":help") => println!("{}", HELP),
+            Some(":type") => {
+                let name = words.next().ok_or("Usage: :type A")?;
+                match self.program.get_signature(self.agent(name)?) {
+                    Some(t) => println!("{} : {}", name, self.program.show_signature(&t)),
+                    None => println!("{} has no type", name),
+                }
+            }
+            Some(":sub") => {
+                let (Some(a), Some(b)) = (words.next(), words.next()) else {
+                    return Err("Usage: :sub A B".to_string());
+                };
+                if self.program.is_subtype_of(self.agent(a)?, self.agent(b)?) {
+                    println!("{} <= {}", a, b);
+                } else {
+                    println!("{} is not a subtype of {}", a, b);
+                }
+            }
+            Some(":rules") => {
+                let name = words.next().ok_or("Usage: :rules A")?;
+                let agent = self.agent(name)?;
+                let agents: Vec<_> = self
+                    .program
+                    .can_connect_to(agent)
+                    .map(|x| self.program.show_agent(x))
+                    .collect();
+                println!("{} ~ {}", name, agents.join(" | "));
+            }
+            Some(":step") => {
+                if let Some((a, b)) = self.net.vars.net.interactions.pop() {
+                    self.net.vars.net.interact(a, b);
+                    print!("{}", self.show_net());
+                } else {
+                    println!("The net is in normal form");
+                }
+            }
+            Some(":normal") => {
+                self.net.vars.net.normal();
+                print!("{}", self.show_net());
+            }
+            Some(":show") => print!("{}", self.show_net()),
+            Some(":clear") => self.net = Self::empty_net(),
+            Some(":reload") => {
+                self.program = Self::load(&self.path)?;
+                self.net = Self::empty_net();
+                println!("Reloaded {}", self.path);
+            }
+            Some(command)
+                if command.starts_with(':')
+                    && command[1..].chars().all(|c| c.is_ascii_alphabetic()) =>
+            {
+                return Err(format!("Unknown command `{}`; try :help", command));
+            }
+            Some(_) => self.add_net(line)?,
+        }
+        Ok(true)
+    }
+}
+
+pub fn run(path: &str) -> Result<(), String> {
+    let mut repl = Repl::new(path)?;
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line.map_err(|e| e.to_string())?;
+        match repl.run_line(line.trim()) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+}
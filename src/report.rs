@@ -0,0 +1,396 @@
+//! Output of diagnostics, reduction traces and normal forms, either as
+//! plain text or as JSON lines.
+
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::{
+    builder::{Definition, ProgramBuilder},
+    checker::{Cell, Exhaustiveness},
+    infer::Inferred,
+    json::Json,
+    lattice::Lattice,
+    run::{AgentId, Net, Tree, VarId},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown format `{}`; expected `text` or `json`", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Severity {
+    #[default]
+    Error,
+    Warning,
+}
+
+/// A problem found by the parser or by one of the checkers.
+///
+/// `message` is the human-readable rendering; `data` holds the same
+/// information in structured form for JSON output.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub check: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Range<usize>>,
+    pub data: Vec<(&'static str, Json)>,
+}
+
+impl Diagnostic {
+    pub fn new(check: &'static str, message: String) -> Self {
+        Self {
+            check,
+            severity: Severity::Error,
+            message,
+            span: None,
+            data: vec![],
+        }
+    }
+    pub fn at(mut self, span: Option<Range<usize>>) -> Self {
+        self.span = span;
+        self
+    }
+    pub fn severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+    pub fn with(mut self, key: &'static str, value: impl Into<Json>) -> Self {
+        self.data.push((key, value.into()));
+        self
+    }
+    pub fn to_json(&self) -> Json {
+        Json::object(
+            [
+                ("type", Json::from("diagnostic")),
+                ("check", Json::from(self.check)),
+                (
+                    "severity",
+                    Json::from(match self.severity {
+                        Severity::Error => "error",
+                        Severity::Warning => "warning",
+                    }),
+                ),
+                ("message", Json::from(self.message.clone())),
+            ]
+            .into_iter()
+            .chain(
+                self.span
+                    .clone()
+                    .map(|x| ("span", Json::from((x.start, x.end)))),
+            )
+            .chain(self.data.iter().map(|(k, v)| (*k, v.clone()))),
+        )
+    }
+}
+
+impl<'i> ProgramBuilder<'i> {
+    pub fn show_tree(&self, net: &Net, scope: &mut BTreeMap<VarId, String>, tree: &Tree) -> String {
+        net.show_tree(&|x| self.show_agent(x), scope, &mut vec![], tree)
+    }
+    pub fn net_to_json(&self, net: &Net, scope: &mut BTreeMap<VarId, String>) -> Json {
+        let mut visited = vec![];
+        let show_agent = |x| self.show_agent(x);
+        let mut pairs = |pairs: &Vec<_>| -> Json {
+            Json::Array(
+                pairs
+                    .iter()
+                    .map(|(a, b)| {
+                        Json::from((
+                            net.show_tree(&show_agent, scope, &mut visited, a),
+                            net.show_tree(&show_agent, scope, &mut visited, b),
+                        ))
+                    })
+                    .collect(),
+            )
+        };
+        let redexes = pairs(&net.interactions);
+        let stuck = pairs(&net.stuck);
+        let mut vars = vec![];
+        for (id, tree) in &net.vars {
+            if let Some(tree) = tree
+                && !visited.contains(id)
+            {
+                visited.push(*id);
+                let name = scope.get(id).cloned().unwrap_or(format!("?{}", id.0));
+                vars.push(Json::from((
+                    name,
+                    net.show_tree(&show_agent, scope, &mut visited, tree),
+                )));
+            }
+        }
+        Json::object([
+            ("redexes", redexes),
+            ("stuck", stuck),
+            ("vars", Json::Array(vars)),
+        ])
+    }
+    pub fn emit(&self, diagnostic: &Diagnostic) {
+        if diagnostic.severity == Severity::Error {
+            self.errors.set(self.errors.get() + 1);
+        }
+        match self.format {
+            Format::Text if diagnostic.severity == Severity::Warning => {
+                println!("Warning: {}", diagnostic.message)
+            }
+            Format::Text => println!("{}", diagnostic.message),
+            Format::Json => println!("{}", diagnostic.to_json()),
+        }
+    }
+    /// Prints the state of a net after the `step`th interaction of a trace.
+    /// `redex` is the active pair that was just reduced.
+    pub fn emit_step(
+        &self,
+        step: usize,
+        redex: (String, String),
+        net: &Net,
+        scope: &mut BTreeMap<VarId, String>,
+    ) {
+        match self.format {
+            Format::Text => {
+                let net = net.show_net_compact(&|id| self.show_agent(id), scope);
+                println!("{}---", net);
+            }
+            Format::Json => println!(
+                "{}",
+                Json::object([
+                    ("type", Json::from("step")),
+                    ("step", Json::from(step)),
+                    ("redex", Json::from(redex)),
+                    ("net", self.net_to_json(net, scope)),
+                ])
+            ),
+        }
+    }
+    pub fn emit_stats(&self, stats: &[(&str, usize)]) {
+        match self.format {
+            Format::Text => {
+                for (name, value) in stats {
+                    println!("{}: {}", name, value);
+                }
+            }
+            Format::Json => println!(
+                "{}",
+                Json::object(
+                    [("type", Json::from("stats"))]
+                        .into_iter()
+                        .chain(stats.iter().map(|(k, v)| (*k, Json::from(*v))))
+                )
+            ),
+        }
+    }
+    pub fn emit_normal_form(&self, net: &Net, scope: &mut BTreeMap<VarId, String>) {
+        match self.format {
+            Format::Text => {
+                let net = net.show_net(&|id| self.show_agent(id), scope, true);
+                println!("{}", net);
+            }
+            Format::Json => println!(
+                "{}",
+                Json::object([
+                    ("type", Json::from("normal_form")),
+                    ("net", self.net_to_json(net, scope)),
+                ])
+            ),
+        }
+    }
+    /// Prints the checking net of a rule, before the steps of its reduction.
+    pub fn emit_rule_check(
+        &self,
+        def: &Definition,
+        net: &Net,
+        scope: &mut BTreeMap<VarId, String>,
+    ) {
+        let left = self.show_agent(def.left_id);
+        let right = self.show_agent(def.right_id);
+        match self.format {
+            Format::Text => {
+                let net = net.show_net_compact(&|id| self.show_agent(id), scope);
+                println!("Checking rule {} = {}:\n{}---", left, right, net);
+            }
+            Format::Json => println!(
+                "{}",
+                Json::object([
+                    ("type", Json::from("rule_check")),
+                    ("rule", Json::from((left, right))),
+                    ("net", self.net_to_json(net, scope)),
+                ])
+            ),
+        }
+    }
+    /// Prints the coverage matrix of a type, with a row per destructor and a
+    /// column per constructor.
+    pub fn emit_exhaustiveness(&self, matrix: &Exhaustiveness) {
+        let name = |x: &AgentId| self.show_agent(*x);
+        let constructors: Vec<_> = matrix.constructors.iter().map(name).collect();
+        let destructors: Vec<_> = matrix.destructors.iter().map(name).collect();
+        match self.format {
+            Format::Text => {
+                let width = destructors.iter().map(|x| x.len()).max().unwrap_or(0);
+                println!("{}:", name(&matrix.ty));
+                let header = format!("  {:width$}  {}", "", constructors.join("  "));
+                println!("{}", header.trim_end());
+                for (destructor, row) in destructors.iter().zip(&matrix.cells) {
+                    let cells: Vec<_> = row
+                        .iter()
+                        .zip(&constructors)
+                        .map(|(cell, constructor)| {
+                            let mark = match cell {
+                                Cell::Defined => "✓",
+                                Cell::Missing => "✗",
+                                Cell::Unneeded => " ",
+                            };
+                            format!("{:width$}", mark, width = constructor.chars().count())
+                        })
+                        .collect();
+                    let row = format!("  {:width$}  {}", destructor, cells.join("  "));
+                    println!("{}", row.trim_end());
+                }
+            }
+            Format::Json => {
+                let mut missing = vec![];
+                for (destructor, row) in destructors.iter().zip(&matrix.cells) {
+                    for (constructor, cell) in constructors.iter().zip(row) {
+                        if *cell == Cell::Missing {
+                            missing.push(Json::from((destructor.clone(), constructor.clone())));
+                        }
+                    }
+                }
+                println!(
+                    "{}",
+                    Json::object([
+                        ("type", Json::from("exhaustiveness")),
+                        ("of", Json::from(name(&matrix.ty))),
+                        ("constructors", Json::from(constructors)),
+                        ("destructors", Json::from(destructors)),
+                        ("missing", Json::Array(missing)),
+                    ])
+                )
+            }
+        }
+    }
+    /// Prints the Hasse diagram of the subtyping lattice as a list of
+    /// covering pairs, followed by its top and bottom.
+    pub fn emit_lattice(&self, lattice: &Lattice) {
+        let class = |i: usize| self.show_type_class(&lattice.classes[i]);
+        match self.format {
+            Format::Text => {
+                println!("Subtyping lattice:");
+                for (sub, sup) in &lattice.edges {
+                    println!("  {} <= {}", class(*sub), class(*sup));
+                }
+                for i in 0..lattice.classes.len() {
+                    if !lattice.edges.iter().any(|(a, b)| *a == i || *b == i) {
+                        println!("  {}", class(i));
+                    }
+                }
+                let classes = |x: &[usize]| x.iter().map(|i| class(*i)).collect::<Vec<_>>();
+                if !lattice.top.is_empty() {
+                    println!("Top: {}", classes(&lattice.top).join(", "));
+                }
+                if !lattice.bottom.is_empty() {
+                    println!("Bot: {}", classes(&lattice.bottom).join(", "));
+                }
+            }
+            Format::Json => {
+                let classes: Vec<_> = lattice
+                    .classes
+                    .iter()
+                    .map(|c| Json::from(c.iter().map(|x| self.show_agent(*x)).collect::<Vec<_>>()))
+                    .collect();
+                println!(
+                    "{}",
+                    Json::object([
+                        ("type", Json::from("lattice")),
+                        ("classes", Json::Array(classes)),
+                        ("edges", Json::from(lattice.edges.clone())),
+                        ("top", Json::from(lattice.top.clone())),
+                        ("bottom", Json::from(lattice.bottom.clone())),
+                    ])
+                )
+            }
+        }
+    }
+    /// Prints the subtyping lattice in Graphviz's DOT language. In JSON
+    /// output, the DOT source goes in the `dot` field.
+    pub fn emit_lattice_dot(&self, lattice: &Lattice) {
+        let dot = self.lattice_to_dot(lattice);
+        match self.format {
+            Format::Text => println!("{}", dot),
+            Format::Json => println!(
+                "{}",
+                Json::object([
+                    ("type", Json::from("lattice_dot")),
+                    ("dot", Json::from(dot))
+                ])
+            ),
+        }
+    }
+    /// Prints the signature inferred for an agent without an annotator rule.
+    pub fn emit_inferred(&self, inferred: &Inferred) {
+        let agent = self.show_agent(inferred.agent);
+        let signature = self.show_signature(&inferred.signature);
+        match self.format {
+            Format::Text => println!("Inferred {} : {}", agent, signature),
+            Format::Json => println!(
+                "{}",
+                Json::object([
+                    ("type", Json::from("inferred")),
+                    ("agent", Json::from(agent)),
+                    ("signature", Json::from(signature)),
+                ])
+            ),
+        }
+    }
+    /// Prints the rule that a declaration such as a signature stands for.
+    pub fn emit_desugared(&self, source: &str, rule: &str) {
+        match self.format {
+            Format::Text => println!("#[ {} ]\n{}", source, rule),
+            Format::Json => println!(
+                "{}",
+                Json::object([
+                    ("type", Json::from("desugared")),
+                    ("source", Json::from(source)),
+                    ("rule", Json::from(rule)),
+                ])
+            ),
+        }
+    }
+    /// Prints a skeleton rule for a missing interaction.
+    pub fn emit_stub(&self, stub: &str) {
+        match self.format {
+            Format::Text => println!("{}", stub),
+            Format::Json => println!(
+                "{}",
+                Json::object([("type", Json::from("stub")), ("rule", Json::from(stub))])
+            ),
+        }
+    }
+    /// Reports the outcome of a `Check` block. The problems themselves are
+    /// emitted as diagnostics.
+    pub fn emit_check(&self, well_typed: bool) {
+        match self.format {
+            Format::Text if well_typed => println!("Net is well typed"),
+            Format::Text => {}
+            Format::Json => println!(
+                "{}",
+                Json::object([
+                    ("type", Json::from("check")),
+                    ("well_typed", Json::from(well_typed)),
+                ])
+            ),
+        }
+    }
+}
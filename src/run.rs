@@ -5,20 +5,45 @@ pub struct AgentId(pub u64, pub u64);
 #[derive(Clone, Debug, Copy, PartialEq, PartialOrd, Ord, Eq)]
 pub struct VarId(pub u64);
 
+/// Where a cell created by an interaction comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Origin {
+    /// Index of the rule among the program's definitions.
+    pub rule: usize,
+    /// The agent, and the auxiliary port of it, that the cell was connected
+    /// to when the rule was applied.
+    pub agent: AgentId,
+    pub port: usize,
+}
+
 #[derive(Clone, Debug)]
 pub enum Tree {
-    Agent { id: AgentId, aux: Vec<Tree> },
-    Var { id: VarId },
+    Agent {
+        id: AgentId,
+        aux: Vec<Tree>,
+        /// `None` for cells that weren't created by a user-defined rule.
+        origin: Option<Origin>,
+    },
+    Var {
+        id: VarId,
+    },
 }
 
 #[derive(Debug)]
 pub struct InteractionRule {
     pub left_ports: Vec<Tree>,
     pub right_ports: Vec<Tree>,
+    /// Index of the definition this rule comes from, or `None` for built-in
+    /// rules. Cells created by built-in rules inherit the origin of the cells
+    /// that interacted.
+    pub rule: Option<usize>,
 }
 
+/// Looks up the rule for an ordered pair of agents.
+pub type RuleLookup = Box<dyn Fn(&AgentId, &AgentId) -> Option<InteractionRule>>;
+
 pub struct InteractionSystem {
-    pub get: Box<dyn Fn(&AgentId, &AgentId) -> Option<InteractionRule>>,
+    pub get: RuleLookup,
 }
 
 impl std::fmt::Debug for InteractionSystem {
@@ -49,6 +74,24 @@ impl InteractionSystem {
     }
 }
 
+/// Which active pair gets reduced next.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Reduce the most recently created pair first.
+    #[default]
+    Lifo,
+    /// Reduce the oldest pair first.
+    Fifo,
+}
+
+/// Gets notified of every interaction performed by `Net::reduce`.
+pub trait ReduceObserver {
+    fn before_interaction(&mut self, _net: &Net, _a: &Tree, _b: &Tree) {}
+    fn after_interaction(&mut self, _net: &Net) {}
+}
+
+impl ReduceObserver for () {}
+
 #[derive(Clone, Debug, Default)]
 pub struct Net {
     pub interactions: Vec<(Tree, Tree)>,
@@ -73,12 +116,18 @@ impl Net {
     fn link(&mut self, a: Tree, b: Tree) {
         self.interactions.push((a, b))
     }
-    fn freshen(&mut self, scope: &mut BTreeMap<VarId, VarId>, tree: &Tree) -> Tree {
+    fn freshen(
+        &mut self,
+        scope: &mut BTreeMap<VarId, VarId>,
+        tree: &Tree,
+        origin: Option<Origin>,
+    ) -> Tree {
         use Tree::*;
         match tree {
-            Agent { id, aux } => Agent {
+            Agent { id, aux, .. } => Agent {
                 id: *id,
-                aux: aux.into_iter().map(|x| self.freshen(scope, x)).collect(),
+                aux: aux.iter().map(|x| self.freshen(scope, x, origin)).collect(),
+                origin,
             },
             Var { id } => match scope.remove(id) {
                 Some(e) => Var { id: e },
@@ -90,31 +139,68 @@ impl Net {
             },
         }
     }
-    fn apply_rule(&mut self, rule: &InteractionRule, left: Vec<Tree>, right: Vec<Tree>) {
+    /// Copies `tree` into the net, giving its variables fresh ids. A variable
+    /// that occurs twice in `tree` stays connected.
+    pub fn instantiate(&mut self, tree: &Tree) -> Tree {
+        self.freshen(&mut BTreeMap::new(), tree, None)
+    }
+    fn apply_rule(
+        &mut self,
+        rule: &InteractionRule,
+        (left_id, left): (AgentId, Vec<Tree>),
+        (right_id, right): (AgentId, Vec<Tree>),
+        inherited: Option<Origin>,
+    ) {
         let mut var_set = BTreeMap::new();
-        for (i, j) in rule
-            .left_ports
-            .iter()
-            .zip(left.into_iter())
-            .chain(rule.right_ports.iter().zip(right.into_iter()))
+        let origin = |agent, port| match rule.rule {
+            Some(rule) => Some(Origin { rule, agent, port }),
+            None => inherited,
+        };
+        let left = rule.left_ports.iter().zip(left).enumerate();
+        let right = rule.right_ports.iter().zip(right).enumerate();
+        for (i, j, origin) in left
+            .map(|(port, (i, j))| (i, j, origin(left_id, port)))
+            .chain(right.map(|(port, (i, j))| (i, j, origin(right_id, port))))
         {
-            let i = self.freshen(&mut var_set, i);
+            let i = self.freshen(&mut var_set, i, origin);
             self.link(i, j);
         }
     }
     pub fn interact(&mut self, a: Tree, b: Tree) {
         use Tree::*;
         match (a, b) {
-            (Agent { id: id1, aux: aux1 }, Agent { id: id2, aux: aux2 }) => {
+            (
+                Agent {
+                    id: id1,
+                    aux: aux1,
+                    origin: origin1,
+                },
+                Agent {
+                    id: id2,
+                    aux: aux2,
+                    origin: origin2,
+                },
+            ) => {
                 let rules = self.system.clone();
                 let (rule, rule_flip) = rules.get_rule(id1, id2);
+                let inherited = origin1.or(origin2);
                 if let Some(r) = rule {
-                    self.apply_rule(&r, aux1, aux2);
+                    self.apply_rule(&r, (id1, aux1), (id2, aux2), inherited);
                 } else if let Some(r) = rule_flip {
-                    self.apply_rule(&r, aux2, aux1);
+                    self.apply_rule(&r, (id2, aux2), (id1, aux1), inherited);
                 } else {
-                    self.stuck
-                        .push((Agent { id: id1, aux: aux1 }, Agent { id: id2, aux: aux2 }));
+                    self.stuck.push((
+                        Agent {
+                            id: id1,
+                            aux: aux1,
+                            origin: origin1,
+                        },
+                        Agent {
+                            id: id2,
+                            aux: aux2,
+                            origin: origin2,
+                        },
+                    ));
                 }
             }
             (a, Var { id }) | (Var { id }, a) => {
@@ -132,6 +218,32 @@ impl Net {
             self.interact(a, b)
         }
     }
+    pub fn next_redex(&mut self, strategy: Strategy) -> Option<(Tree, Tree)> {
+        match strategy {
+            Strategy::Lifo => self.interactions.pop(),
+            Strategy::Fifo if !self.interactions.is_empty() => Some(self.interactions.remove(0)),
+            Strategy::Fifo => None,
+        }
+    }
+    /// Reduces the net until it is in normal form or `fuel` interactions
+    /// have been performed. Returns the number of interactions performed.
+    pub fn reduce(
+        &mut self,
+        strategy: Strategy,
+        fuel: Option<u64>,
+        observer: &mut dyn ReduceObserver,
+    ) -> u64 {
+        let mut steps = 0;
+        while fuel.is_none_or(|fuel| steps < fuel)
+            && let Some((a, b)) = self.next_redex(strategy)
+        {
+            observer.before_interaction(self, &a, &b);
+            self.interact(a, b);
+            steps += 1;
+            observer.after_interaction(self);
+        }
+        steps
+    }
 
     pub fn show_net_compact(
         &self,
@@ -150,11 +262,11 @@ impl Net {
         use std::fmt::Write;
         let mut s = String::new();
         for (a, b) in &self.interactions {
-            write!(
+            writeln!(
                 &mut s,
-                "{} = {}\n",
-                self.show_tree(show_agent, scope, &mut visited, &a),
-                self.show_tree(show_agent, scope, &mut visited, &b)
+                "{} = {}",
+                self.show_tree(show_agent, scope, &mut visited, a),
+                self.show_tree(show_agent, scope, &mut visited, b)
             )
             .unwrap();
         }
@@ -166,22 +278,22 @@ impl Net {
                 if let Some(b) = b {
                     visited.push(*a);
                     let a = scope.get(a).cloned().unwrap_or(format!("?{}", a.0));
-                    write!(
+                    writeln!(
                         &mut s,
-                        "{} = {}\n",
+                        "{} = {}",
                         a,
-                        self.show_tree(show_agent, scope, &mut visited, &b)
+                        self.show_tree(show_agent, scope, &mut visited, b)
                     )
                     .unwrap();
                 }
             }
         }
         for (a, b) in &self.stuck {
-            write!(
+            writeln!(
                 &mut s,
-                "{} = {}\n",
-                self.show_tree(show_agent, scope, &mut visited, &a),
-                self.show_tree(show_agent, scope, &mut visited, &b)
+                "{} = {}",
+                self.show_tree(show_agent, scope, &mut visited, a),
+                self.show_tree(show_agent, scope, &mut visited, b)
             )
             .unwrap();
         }
@@ -195,7 +307,7 @@ impl Net {
         tree: &Tree,
     ) -> String {
         match tree {
-            Tree::Agent { id, aux } => {
+            Tree::Agent { id, aux, .. } => {
                 use std::fmt::Write;
                 let mut s = String::new();
                 write!(&mut s, "{}", show_agent(*id)).unwrap();
@@ -229,9 +341,10 @@ impl Net {
     }
     pub fn substitute_ref(&self, tree: &Tree) -> Tree {
         match tree {
-            Tree::Agent { id, aux } => Tree::Agent {
+            Tree::Agent { id, aux, origin } => Tree::Agent {
                 id: *id,
-                aux: aux.into_iter().map(|x| self.substitute_ref(x)).collect(),
+                aux: aux.iter().map(|x| self.substitute_ref(x)).collect(),
+                origin: *origin,
             },
             Tree::Var { id } => {
                 if let Some(Some(b)) = self.vars.get(id) {
@@ -244,9 +357,10 @@ impl Net {
     }
     pub fn substitute(&mut self, tree: Tree) -> Tree {
         match tree {
-            Tree::Agent { id, aux } => Tree::Agent {
+            Tree::Agent { id, aux, origin } => Tree::Agent {
                 id,
                 aux: aux.into_iter().map(|x| self.substitute(x)).collect(),
+                origin,
             },
             Tree::Var { id } => {
                 if let Some(b) = self.vars.get_mut(&id).unwrap().take() {
@@ -0,0 +1,258 @@
+//! Signature declarations, such as `Bool.and : ~Bool(~Bool Bool)`, which
+//! are sugar for annotator rules.
+
+use TSPL::Parser;
+
+use crate::{builder::ProgramBuilder, syntax::Tree};
+
+fn annotate(value: Tree, ty: Tree) -> Tree {
+    Tree::Agent(
+        "::".to_string(),
+        vec![Tree::Agent(":".to_string(), vec![value, ty])],
+    )
+}
+
+impl<'i> ProgramBuilder<'i> {
+    /// Parses the type after `name :` and returns the annotator rule that it
+    /// stands for. `Bool.and : ~Bool(~Bool Bool)` becomes
+    ///
+    /// ```text
+    /// ::(:(Bool.and(x0 x1) ~Bool)) ~ Bool.and(::(:(x0 ~Bool)) ::(:(x1 Bool)))
+    /// ```
+    ///
+    /// A principal type with arguments takes the auxiliary ports' types in a
+    /// second pair of parentheses, as in `List.nil : List(Bot)()`. `_` leaves
+    /// a port unannotated.
+    pub fn parse_signature(&mut self, name: Tree) -> Result<(Tree, Tree), String> {
+        let Tree::Agent(name, args) = name else {
+            return Err("Expected an agent name before `:`".to_string());
+        };
+        if !args.is_empty() {
+            return Err(format!("Expected `{} :`, without auxiliary ports", name));
+        }
+        let ty = self.parse_tree()?;
+        self.skip_trivia();
+        let (principal, ports) = if self.peek_one() == Some('(') {
+            self.consume("(")?;
+            let mut ports = vec![];
+            while self.peek_one() != Some(')') {
+                ports.push(self.parse_tree()?);
+                self.skip_trivia();
+            }
+            self.consume(")")?;
+            (ty, ports)
+        } else {
+            match ty {
+                Tree::Agent(ty, ports) => (Tree::Agent(ty, vec![]), ports),
+                Tree::Var(_) => (ty, vec![]),
+            }
+        };
+        self.signature_rule(&name, principal, ports)
+    }
+    /// The annotator rule that gives `name` the principal type `principal`
+    /// and the auxiliary port types `ports`.
+    pub fn signature_rule(
+        &mut self,
+        name: &str,
+        principal: Tree,
+        ports: Vec<Tree>,
+    ) -> Result<(Tree, Tree), String> {
+        let source = show_signature(name, &principal, &ports);
+        let principal = dual_vars(principal)?;
+        let ports: Vec<_> = ports.into_iter().map(dual_vars).collect::<Result<_, _>>()?;
+        check_lone_vars(name, &principal, &ports)?;
+        let (principal, ports) = expand_type_vars(principal, ports);
+        let vars: Vec<_> = (0..ports.len()).map(|i| format!("x{}", i)).collect();
+        let left = annotate(
+            Tree::Agent(
+                name.to_string(),
+                vars.iter().cloned().map(Tree::Var).collect(),
+            ),
+            principal,
+        );
+        let right = Tree::Agent(
+            name.to_string(),
+            vars.into_iter()
+                .zip(ports)
+                .map(|(var, ty)| match ty {
+                    Tree::Agent(ty, args) if ty == "_" && args.is_empty() => Tree::Var(var),
+                    ty => annotate(Tree::Var(var), ty),
+                })
+                .collect(),
+        );
+        self.desugared
+            .push((source, format!("{} ~ {}", left, right)));
+        Ok((left, right))
+    }
+}
+
+/// Shows a signature the way it is written, as in `Bool.and : ~Bool(~Bool Bool)`.
+fn show_signature(name: &str, principal: &Tree, ports: &[Tree]) -> String {
+    let has_arguments = matches!(principal, Tree::Agent(_, args) if !args.is_empty());
+    if ports.is_empty() && !has_arguments {
+        return format!("{} : {}", name, principal);
+    }
+    let ports: Vec<_> = ports.iter().map(|x| x.to_string()).collect();
+    format!("{} : {}({})", name, principal, ports.join(" "))
+}
+
+/// Reads `~a`, which the parser takes for a cosymbol, as the dual `~(a)` of
+/// the type variable `a`.
+fn dual_vars(tree: Tree) -> Result<Tree, String> {
+    match tree {
+        Tree::Agent(name, aux) => match name.strip_prefix('~') {
+            Some(var) if var.starts_with(|c: char| c.is_ascii_lowercase()) => {
+                if !aux.is_empty() {
+                    return Err(format!("Type variable `{}` can't have arguments", var));
+                }
+                Ok(Tree::Agent(
+                    "~".to_string(),
+                    vec![Tree::Var(var.to_string())],
+                ))
+            }
+            _ => Ok(Tree::Agent(
+                name,
+                aux.into_iter().map(dual_vars).collect::<Result<_, _>>()?,
+            )),
+        },
+        var => Ok(var),
+    }
+}
+
+/// Rejects type variables that are used only once, in an auxiliary port.
+/// Nothing links such a variable to another port, so the port would accept
+/// or give out values of any type. A single use in the principal type, as in
+/// `List.nil : List(a)()`, is fine: the agent itself can have any such type.
+fn check_lone_vars(name: &str, principal: &Tree, ports: &[Tree]) -> Result<(), String> {
+    fn vars<'t>(tree: &'t Tree, out: &mut Vec<&'t str>) {
+        match tree {
+            Tree::Agent(_, aux) => aux.iter().for_each(|x| vars(x, out)),
+            Tree::Var(var) => out.push(var),
+        }
+    }
+    let mut all = vec![];
+    for tree in std::iter::once(principal).chain(ports) {
+        vars(tree, &mut all);
+    }
+    for (i, port) in ports.iter().enumerate() {
+        let mut used = vec![];
+        vars(port, &mut used);
+        if let Some(var) = used
+            .iter()
+            .find(|x| all.iter().filter(|y| y == x).count() == 1)
+        {
+            return Err(format!(
+                "Type variable `{}` is only used in port {} of `{}`, which would then have any type; \
+                use it in another port too, or give the port a type",
+                var, i, name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// An occurrence of a type variable, renamed apart from the others.
+struct Occurrence {
+    var: String,
+    name: String,
+    /// Whether it is under an odd number of cosymbols, as in `~List(a)`.
+    negative: bool,
+    /// Whether it makes up the whole type of an auxiliary port.
+    bare_port: bool,
+}
+
+/// Renames the occurrences of each type variable apart, and then links the
+/// occurrences of a variable used more than once with `&` cells, which
+/// copy the types that reach them.
+///
+/// The first occurrence that makes up the whole type of an auxiliary port
+/// (or else the first occurrence) is where the type comes in, so it is
+/// replaced by the `&` cells, whose other ports go to the remaining
+/// occurrences. Those with the opposite polarity go through a `~` cell,
+/// since the dual of `List(a)` is `~List(~a)`. With `a` used three times,
+/// `List.cons : List(a)(a ~List(a))` becomes
+///
+/// ```text
+/// ::(:(List.cons(x0 x1) List(a.0))) ~ List.cons(::(:(x0 &(a.0 ~(a.2)))) ::(:(x1 ~List(a.2))))
+/// ```
+fn expand_type_vars(principal: Tree, ports: Vec<Tree>) -> (Tree, Vec<Tree>) {
+    fn rename(
+        tree: Tree,
+        negative: bool,
+        bare_port: bool,
+        occurrences: &mut Vec<Occurrence>,
+    ) -> Tree {
+        match tree {
+            Tree::Agent(name, aux) => {
+                let negative = negative ^ name.starts_with('~');
+                let aux = aux
+                    .into_iter()
+                    .map(|x| rename(x, negative, false, occurrences))
+                    .collect();
+                Tree::Agent(name, aux)
+            }
+            Tree::Var(var) => {
+                let name = format!("{}.{}", var, occurrences.len());
+                occurrences.push(Occurrence {
+                    var,
+                    name: name.clone(),
+                    negative,
+                    bare_port,
+                });
+                Tree::Var(name)
+            }
+        }
+    }
+    fn replace(tree: Tree, name: &str, by: &mut Option<Tree>) -> Tree {
+        match tree {
+            Tree::Var(x) if x == name => by.take().unwrap(),
+            Tree::Agent(agent, aux) => Tree::Agent(
+                agent,
+                aux.into_iter().map(|x| replace(x, name, by)).collect(),
+            ),
+            tree => tree,
+        }
+    }
+    let mut occurrences = vec![];
+    let mut principal = rename(principal, false, false, &mut occurrences);
+    let mut ports: Vec<_> = ports
+        .into_iter()
+        .map(|x| rename(x, false, true, &mut occurrences))
+        .collect();
+    let mut vars: Vec<_> = occurrences.iter().map(|x| x.var.clone()).collect();
+    vars.sort();
+    vars.dedup();
+    for var in vars {
+        let uses: Vec<_> = occurrences.iter().filter(|x| x.var == var).collect();
+        if uses.len() < 2 {
+            continue;
+        }
+        let source = uses.iter().position(|x| x.bare_port).unwrap_or(0);
+        let copies: Vec<_> = uses
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != source)
+            .map(|(_, x)| {
+                let copy = Tree::Var(x.name.clone());
+                if x.negative != uses[source].negative {
+                    Tree::Agent("~".to_string(), vec![copy])
+                } else {
+                    copy
+                }
+            })
+            .collect();
+        let dups = copies
+            .into_iter()
+            .rev()
+            .reduce(|rest, copy| Tree::Agent("&".to_string(), vec![copy, rest]))
+            .unwrap();
+        let mut by = Some(dups);
+        let name = &uses[source].name;
+        principal = replace(principal, name, &mut by);
+        ports = ports
+            .into_iter()
+            .map(|x| replace(x, name, &mut by))
+            .collect();
+    }
+    (principal, ports)
+}
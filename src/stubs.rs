@@ -0,0 +1,70 @@
+//! Skeleton rules for the interactions that the completeness check finds
+//! missing.
+
+use crate::{
+    builder::ProgramBuilder,
+    checker::name_type_vars,
+    run::{AgentId, Net, Tree},
+};
+
+impl<'i> ProgramBuilder<'i> {
+    /// A stub for every missing rule, such as
+    ///
+    /// ```text
+    /// #[ ?_0 : Bool ]
+    /// Bool.not(?_0) ~ Bool.maybe
+    /// ```
+    ///
+    /// The auxiliary ports are filled with the holes `?_0`, `?_1`, ..., and
+    /// the comment above the rule gives their annotated types. The
+    /// destructor, the agent whose type is negative, goes on the left.
+    pub fn stub_rules(&self) -> Vec<String> {
+        self.missing_rules()
+            .into_iter()
+            .map(|missing| {
+                let (mut a, mut b) = missing.agents;
+                if missing.types.0.1 == 0 && missing.types.1.1 > 0 {
+                    std::mem::swap(&mut a, &mut b);
+                }
+                self.stub_rule(a, b)
+            })
+            .collect()
+    }
+    fn stub_rule(&self, left: AgentId, right: AgentId) -> String {
+        let net = Net::default();
+        let signatures = [self.get_signature(left), self.get_signature(right)];
+        let types: Vec<Option<&Tree>> = [left, right]
+            .iter()
+            .zip(&signatures)
+            .flat_map(|(agent, signature)| {
+                let arity = self.arities.get(agent).copied().unwrap_or(0) as usize;
+                (0..arity).map(move |i| signature.as_ref().and_then(|s| s.aux.get(i)?.as_ref()))
+            })
+            .collect();
+        let mut scope = name_type_vars(types.iter().flatten().copied());
+        let mut next = 0;
+        let mut side = |agent: AgentId| {
+            let arity = self.arities.get(&agent).copied().unwrap_or(0) as usize;
+            let name = self.show_agent(agent);
+            if arity == 0 {
+                return name;
+            }
+            let ports: Vec<_> = (next..next + arity).map(|i| format!("?_{}", i)).collect();
+            next += arity;
+            format!("{}({})", name, ports.join(" "))
+        };
+        let rule = format!("{} ~ {}", side(left), side(right));
+        if types.is_empty() {
+            return rule;
+        }
+        let comment: Vec<_> = types
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| match ty {
+                Some(ty) => format!("?_{} : {}", i, self.show_tree(&net, &mut scope, ty)),
+                None => format!("?_{} : _", i),
+            })
+            .collect();
+        format!("#[ {} ]\n{}", comment.join("  "), rule)
+    }
+}
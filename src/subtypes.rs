@@ -0,0 +1,84 @@
+//! `Sub` declarations, which state that one type is a subtype of another.
+
+use TSPL::Parser;
+
+use crate::{
+    builder::{ProgramBuilder, Subtype},
+    report::Diagnostic,
+    syntax::Tree,
+};
+
+impl<'i> ProgramBuilder<'i> {
+    /// Parses the inside of `Sub[Nat <= Int]`, which defines `~Int ~ Nat`:
+    /// everything that consumes an `Int` can consume a `Nat`.
+    pub fn parse_sub(&mut self, start: usize) -> Result<(), String> {
+        let sub = self.parse_ctr_name()?;
+        self.skip_trivia();
+        self.consume("<=")?;
+        let sup = self.parse_ctr_name()?;
+        self.skip_trivia();
+        self.consume("]")?;
+        let left = Tree::Agent(format!("~{}", sup), vec![]);
+        let right = Tree::Agent(sub.clone(), vec![]);
+        self.desugared.push((
+            format!("Sub[{} <= {}]", sub, sup),
+            format!("{} ~ {}", left, right),
+        ));
+        self.define_rule(left, right, start, false)?;
+        self.subtypes.push(Subtype {
+            sub,
+            sup,
+            span: self.span_from(start),
+        });
+        Ok(())
+    }
+    /// Checks that every `Sub[A <= B]` declaration holds according to
+    /// `is_subtype_of`. It doesn't when some agent interacts with `B` but
+    /// not with `A`.
+    pub fn check_subtypes(&self) -> Vec<Diagnostic> {
+        let system = self.interaction_system.as_ref().unwrap().clone();
+        let mut diagnostics = vec![];
+        for subtype in &self.subtypes {
+            let (Some(sub), Some(sup)) = (
+                self.lookup_agent(&subtype.sub),
+                self.lookup_agent(&subtype.sup),
+            ) else {
+                continue;
+            };
+            if self.is_subtype_of(sub, sup) {
+                continue;
+            }
+            // Find the agents that witness the failure, for the message.
+            let violations: Vec<_> = self
+                .can_connect_to(sup)
+                .filter(|a| !system.has_rule(*a, sub))
+                .map(|a| self.show_agent(a))
+                .collect();
+            let lines: Vec<_> = violations
+                .iter()
+                .map(|a| {
+                    format!(
+                        "\t{a} ~ {} is defined, but {a} ~ {} isn't",
+                        subtype.sup, subtype.sub
+                    )
+                })
+                .collect();
+            diagnostics.push(
+                Diagnostic::new(
+                    "sub",
+                    format!(
+                        "Subtype declaration {} <= {} doesn't hold:\n{}",
+                        subtype.sub,
+                        subtype.sup,
+                        lines.join("\n")
+                    ),
+                )
+                .at(Some(subtype.span.clone()))
+                .with("sub", subtype.sub.as_str())
+                .with("sup", subtype.sup.as_str())
+                .with("missing", violations),
+            );
+        }
+        diagnostics
+    }
+}
@@ -1,12 +1,33 @@
-use crate::builder::ProgramBuilder;
+use crate::{
+    builder::{BlockKind, PendingBlock, ProgramBuilder},
+    reduce::ReduceOptions,
+};
 use TSPL::Parser;
 
-#[derive(Debug)]
+/// Macros that change the program, and so can't appear inside a tree.
+const SCOPE_MACROS: [&str; 8] = [
+    "Trace", "Data", "Derive", "Sub", "Trait", "Impl", "Reduce", "Check",
+];
+
+#[derive(Debug, Clone)]
 pub enum Tree {
     Agent(String, Vec<Tree>),
     Var(String),
 }
 
+impl std::fmt::Display for Tree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tree::Agent(name, aux) if aux.is_empty() => write!(f, "{}", name),
+            Tree::Agent(name, aux) => {
+                let aux: Vec<_> = aux.iter().map(|x| x.to_string()).collect();
+                write!(f, "{}({})", name, aux.join(" "))
+            }
+            Tree::Var(name) => write!(f, "{}", name),
+        }
+    }
+}
+
 impl<'i> ProgramBuilder<'i> {
     pub fn new(input: &'i str) -> Self {
         let mut a = Self {
@@ -18,15 +39,59 @@ impl<'i> ProgramBuilder<'i> {
             agent_scope_back: Default::default(),
             next_agent_id: 0,
             interaction_system: None,
+            format: Default::default(),
+            pending: vec![],
+            rules_so_far: false,
+            traced_rules: vec![],
+            trace_all_rules: false,
+            strict_typing: false,
+            desugared: vec![],
+            data_types: Default::default(),
+            derived: vec![],
+            traits: vec![],
+            impls: vec![],
+            subtypes: vec![],
+            errors: Default::default(),
+            agent_spans: Default::default(),
+            redex_spans: vec![],
         };
         a.enter();
         a
     }
+    /// Moves the builder over to a new input, keeping everything that has
+    /// been defined so far. Used to parse more items against a loaded program.
+    pub fn with_input<'j>(self, input: &'j str) -> ProgramBuilder<'j> {
+        ProgramBuilder {
+            input,
+            index: 0,
+            def: self.def,
+            levels: self.levels,
+            arities: self.arities,
+            agent_scope_back: self.agent_scope_back,
+            next_agent_id: self.next_agent_id,
+            interaction_system: self.interaction_system,
+            format: self.format,
+            pending: self.pending,
+            rules_so_far: self.rules_so_far,
+            traced_rules: self.traced_rules,
+            trace_all_rules: self.trace_all_rules,
+            strict_typing: self.strict_typing,
+            desugared: self.desugared,
+            data_types: self.data_types,
+            derived: self.derived,
+            traits: self.traits,
+            impls: self.impls,
+            subtypes: self.subtypes,
+            errors: self.errors,
+            agent_spans: self.agent_spans,
+            redex_spans: self.redex_spans,
+        }
+    }
 }
 
 impl<'i> Parser<'i> for ProgramBuilder<'i> {
     fn input(&mut self) -> &'i str {
-        &self.input
+        self.input
     }
     fn index(&mut self) -> &mut usize {
         &mut self.index
@@ -34,7 +99,7 @@ impl<'i> Parser<'i> for ProgramBuilder<'i> {
 }
 
 impl<'i> ProgramBuilder<'i> {
-    fn is_name_character(c: char) -> bool {
+    pub fn is_name_character(c: char) -> bool {
         c.is_ascii_alphanumeric() || ".!#$%&/?*-_:;".contains(c)
     }
 
@@ -45,10 +110,9 @@ impl<'i> ProgramBuilder<'i> {
             .ok_or("Expected a character for var_name.")?;
         if first.is_ascii_lowercase() {
             self.advance_one();
-            let rest = self.take_while(|c| Self::is_name_character(c));
+            let rest = self.take_while(Self::is_name_character);
             return Ok(format!("{}{}", first, rest));
         }
-        println!("{:?}", &self.input[self.index..]);
         Err("Expected a variable name.".to_string())
     }
 
@@ -57,10 +121,9 @@ impl<'i> ProgramBuilder<'i> {
         let first = self
             .peek_one()
             .ok_or("Expected a character for ctr_name.")?;
-        if !first.is_ascii_lowercase() && !" ()[]{}=".contains(first)
-        {
+        if !first.is_ascii_lowercase() && !" ()[]{}=".contains(first) {
             self.advance_one();
-            let rest = self.take_while(|c| Self::is_name_character(c));
+            let rest = self.take_while(Self::is_name_character);
             Ok(format!("{}{}", first, rest))
         } else {
             Err("Expected an agent name.".to_string())
@@ -68,64 +131,119 @@ impl<'i> ProgramBuilder<'i> {
     }
 
     pub fn parse_macro(&mut self) -> Result<usize, String> {
+        self.skip_trivia();
+        let start = self.index;
         let ctr_name = self.parse_ctr_name()?;
         self.consume("[")?;
-        if ctr_name == "Reduce" {
-            self.enter();
-            self.skip_trivia();
-            self.consume("{")?;
-            self.parse_scope()?;
+        if ctr_name == "Trace" {
+            let left = self.parse_ctr_name()?;
+            let right = self.parse_ctr_name()?;
+            self.traced_rules.push((left, right));
             self.skip_trivia();
-            self.consume("}")?;
-            let mut result = self.exit().unwrap();
-            result.vars.net.system = self.build_interaction_system();
-
-            if true {
-                // reduce and show each step
-                while let Some((a, b)) = result.vars.net.interactions.pop() {
-                    result.vars.net.interact(a, b);
-                    let mut vars = result
-                        .vars
-                        .var_scope
-                        .iter()
-                        .map(|(k, v)| (v.clone(), k.clone()))
-                        .collect();
-                    let net = result.vars.net.show_net_compact(
-                        &|id| self.agent_scope_back.get(&id).unwrap().to_string(),
-                        &mut vars,
-                    );
-                    println!("{}---", net);
-                }
-            } else {
-                result.vars.net.normal();
-                let mut vars = result
-                    .vars
-                    .var_scope
-                    .iter()
-                    .map(|(k, v)| (v.clone(), k.clone()))
-                    .collect();
-                let net = result.vars.net.show_net_compact(
-                    &|id| self.agent_scope_back.get(&id).unwrap().to_string(),
-                    &mut vars,
-                );
-                println!("{}", net);
-            }
-        } else {
+            self.consume("]")?;
+            return Ok(self.index);
+        }
+        if ctr_name == "Data" {
+            self.parse_data(start)?;
+            return Ok(self.index);
+        }
+        if ctr_name == "Derive" {
+            self.parse_derive(start)?;
+            return Ok(self.index);
+        }
+        if ctr_name == "Sub" {
+            self.parse_sub(start)?;
+            return Ok(self.index);
+        }
+        if ctr_name == "Trait" {
+            self.parse_trait(start)?;
+            return Ok(self.index);
+        }
+        if ctr_name == "Impl" {
+            self.parse_impl(start)?;
+            return Ok(self.index);
+        }
+        if ctr_name != "Reduce" && ctr_name != "Check" {
             let _ = self.take_while(|c| c != ']'); // Assumes any sequence inside []
+            self.consume("]")?;
+            return Ok(self.index);
         }
+        let kind = if ctr_name == "Reduce" {
+            BlockKind::Reduce(self.parse_reduce_options()?)
+        } else {
+            BlockKind::Check
+        };
+        self.enter();
+        self.skip_trivia();
+        self.consume("{")?;
+        self.parse_scope()?;
+        self.skip_trivia();
+        self.consume("}")?;
+        let mut level = self.exit().unwrap();
+        // Agents first mentioned here may get rules further down.
+        self.levels
+            .last_mut()
+            .unwrap()
+            .agent_scope
+            .extend(std::mem::take(&mut level.agent_scope));
+        self.skip_trivia();
         self.consume("]")?;
+        self.pending.push(PendingBlock {
+            kind,
+            level,
+            rules_before: self.def.len(),
+            span: start..self.index,
+        });
         Ok(self.index)
     }
 
+    /// Parses `key=value` pairs and flags up to the opening brace of a `Reduce` block.
+    fn parse_reduce_options(&mut self) -> Result<ReduceOptions, String> {
+        let mut options = ReduceOptions::default();
+        loop {
+            self.skip_trivia();
+            if self.peek_one() == Some('{') {
+                return Ok(options);
+            }
+            let key = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            if key.is_empty() {
+                return Err("Expected a Reduce option or `{`".to_string());
+            }
+            self.skip_trivia();
+            // Flags such as `unchecked` don't need a value.
+            let value = if self.peek_one() == Some('=') {
+                self.consume("=")?;
+                self.skip_trivia();
+                self.take_while(Self::is_name_character)
+            } else {
+                ""
+            };
+            options.set(key, value)?;
+        }
+    }
+
     pub fn parse_tree(&mut self) -> Result<Tree, String> {
         self.skip_trivia();
-        let mut old_idx = self.index;
-        while let Ok(m) = self.parse_macro() {
-            old_idx = m;
+        // Only comment macros may appear inside a tree; the others change the
+        // program and belong at the top level of a scope.
+        while self.peek_macro()? {
+            let index = self.index;
+            let ctr_name = self.parse_ctr_name()?;
+            if SCOPE_MACROS.contains(&ctr_name.as_str()) {
+                return Err(format!(
+                    "`{}` can only be used at the top level of a scope, not inside a tree",
+                    ctr_name
+                ));
+            }
+            self.index = index;
+            self.parse_macro()?;
+            self.skip_trivia();
         }
-        self.index = old_idx;
         let old_idx = self.index;
         if let Ok(ctr_name) = self.parse_ctr_name() {
+            self.agent_spans
+                .entry(ctr_name.clone())
+                .or_insert(old_idx..self.index);
             self.skip_trivia();
             if self.peek_one() == Some('(') {
                 self.consume("(")?;
@@ -0,0 +1,126 @@
+//! `Trait` declarations and `Impl` blocks, which group the rules that make
+//! a type implement a trait.
+
+use TSPL::Parser;
+
+use crate::{
+    builder::{Impl, ProgramBuilder},
+    report::Diagnostic,
+    run::AgentId,
+    syntax::Tree,
+};
+
+impl<'i> ProgramBuilder<'i> {
+    /// Parses the inside of `Trait[Erasure]`, which declares the type
+    /// `~Erasure` of the agents that make up the trait.
+    pub fn parse_trait(&mut self, start: usize) -> Result<(), String> {
+        let name = self.parse_ctr_name()?;
+        self.skip_trivia();
+        self.consume("]")?;
+        let cotype = format!("~{}", name);
+        let kind = Tree::Agent("Type".to_string(), vec![]);
+        let (left, right) = self.signature_rule(&cotype, kind, vec![])?;
+        self.define_rule(left, right, start, false)?;
+        self.traits.push(name);
+        Ok(())
+    }
+    /// Parses the inside of `Impl[Erasure for Bool { ... }]`, which defines
+    /// `~Erasure ~ Bool` and the rules in the braces. Those rules should be
+    /// the ones between the agents of type `~Erasure` and the constructors
+    /// of `Bool`.
+    pub fn parse_impl(&mut self, start: usize) -> Result<(), String> {
+        let trait_name = self.parse_ctr_name()?;
+        if !self.traits.contains(&trait_name) {
+            return Err(format!(
+                "`{}` must be declared with `Trait` before implementing it",
+                trait_name
+            ));
+        }
+        self.skip_trivia();
+        let keyword = self.parse_var_name()?;
+        if keyword != "for" {
+            return Err(format!("Expected `for`, found `{}`", keyword));
+        }
+        let ty = self.parse_ctr_name()?;
+        self.skip_trivia();
+        self.consume("{")?;
+        let rules_start = self.def.len();
+        self.parse_scope()?;
+        let rules = rules_start..self.def.len();
+        self.consume("}")?;
+        self.skip_trivia();
+        self.consume("]")?;
+        let cotype = Tree::Agent(format!("~{}", trait_name), vec![]);
+        self.define_rule(cotype, Tree::Agent(ty.clone(), vec![]), start, false)?;
+        self.impls.push(Impl {
+            trait_name,
+            ty,
+            rules,
+            span: self.span_from(start),
+        });
+        Ok(())
+    }
+    /// Whether the rules between the instances of two types belong in an
+    /// `Impl` block.
+    pub fn in_impl(&self, a: AgentId, b: AgentId) -> bool {
+        self.impls.iter().any(|i| {
+            let cotype = self.lookup_agent(&format!("~{}", i.trait_name));
+            let ty = self.lookup_agent(&i.ty);
+            (cotype, ty) == (Some(a), Some(b)) || (cotype, ty) == (Some(b), Some(a))
+        })
+    }
+    /// The rules that each `Impl` block is missing: one for each agent of
+    /// type `~Trait` and constructor of the type whose types interact, but
+    /// that isn't defined in the block.
+    pub fn check_impls(&self) -> Vec<Diagnostic> {
+        let instances = self.instances_by_type();
+        let mut diagnostics = vec![];
+        for i in &self.impls {
+            let (Some(cotype), Some(ty)) = (
+                self.lookup_agent(&format!("~{}", i.trait_name)),
+                self.lookup_agent(&i.ty),
+            ) else {
+                continue;
+            };
+            let (Some(destructors), Some(constructors)) =
+                (instances.get(&cotype), instances.get(&ty))
+            else {
+                continue;
+            };
+            let mut missing = vec![];
+            for (d, d_type) in destructors {
+                for (c, c_type) in constructors {
+                    let defined = self.def[i.rules.clone()].iter().any(|x| {
+                        (x.left_id, x.right_id) == (*d, *c) || (x.left_id, x.right_id) == (*c, *d)
+                    });
+                    if !defined && self.types_interact(d_type, c_type) {
+                        missing.push((self.show_agent(*d), self.show_agent(*c)));
+                    }
+                }
+            }
+            if missing.is_empty() {
+                continue;
+            }
+            let rules: Vec<_> = missing
+                .iter()
+                .map(|(d, c)| format!("\t{} ~ {}", d, c))
+                .collect();
+            diagnostics.push(
+                Diagnostic::new(
+                    "impl",
+                    format!(
+                        "Impl {} for {} is missing rules:\n{}",
+                        i.trait_name,
+                        i.ty,
+                        rules.join("\n")
+                    ),
+                )
+                .at(Some(i.span.clone()))
+                .with("trait", i.trait_name.as_str())
+                .with("for", i.ty.as_str())
+                .with("missing", missing),
+            );
+        }
+        diagnostics
+    }
+}
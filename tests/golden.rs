@@ -0,0 +1,259 @@
+//! Runs the files in `tests/golden` and compares the output with the
+//! `.out` files next to them.
+
+mod common;
+
+use std::process::Output;
+
+use common::{path, run, stdout};
+
+/// Runs `tests/golden/{input}.itt` with `args`, and checks that it prints
+/// `tests/golden/{expected}.out`.
+fn golden(input: &str, expected: &str, args: &[&str]) -> Output {
+    let file = format!("tests/golden/{}.itt", input);
+    let output = run(&[args, &[file.as_str()]].concat(), "");
+    let expected =
+        std::fs::read_to_string(path(&format!("tests/golden/{}.out", expected))).unwrap();
+    assert_eq!(stdout(&output), expected, "output of {} {:?}", file, args);
+    output
+}
+
+#[test]
+fn coverage() {
+    let output = golden("coverage", "coverage", &[]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn strict_typing_fails() {
+    let output = run(&["--strict-typing", "tests/golden/coverage.itt"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("Typing coverage"));
+}
+
+/// The type argument of `List(a)` isn't an unannotated auxiliary port.
+#[test]
+fn coverage_type_args() {
+    let output = golden(
+        "coverage_type_args",
+        "empty",
+        &["--coverage", "--strict-typing"],
+    );
+    assert!(output.status.success());
+}
+
+#[test]
+fn exhaustiveness() {
+    golden("coverage", "exhaustiveness", &["--exhaustiveness"]);
+}
+
+#[test]
+fn lattice_dot_json() {
+    let output = golden(
+        "coverage",
+        "lattice_dot",
+        &["--format", "json", "--lattice-dot"],
+    );
+    // Every line is a JSON object.
+    assert!(
+        stdout(&output)
+            .lines()
+            .all(|x| x.starts_with('{') && x.ends_with('}'))
+    );
+}
+
+/// The `Trait` and `Impl` example from the README, which must produce no
+/// diagnostics.
+#[test]
+fn trait_impl() {
+    let output = golden("trait", "empty", &[]);
+    assert!(output.status.success());
+}
+
+/// The `Data` and `Derive` examples from the README, with whitespace other
+/// than spaces around `for`.
+#[test]
+fn data_derive() {
+    let output = golden("derive", "derive", &[]);
+    assert!(output.status.success());
+    golden("derive", "derive_desugared", &["--show-desugared"]);
+}
+
+/// A `Sub` declaration that holds, whose generated rule must not be reported.
+#[test]
+fn sub() {
+    let output = golden("sub", "sub", &[]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn sub_violation() {
+    let output = golden("sub_violation", "sub_violation", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// Inference of types with arguments, for agents with many ports.
+#[test]
+fn infer() {
+    let output = golden("infer", "infer", &["--infer"]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn infer_ambiguous() {
+    let output = golden("infer_ambiguous", "infer_ambiguous", &["--infer"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// A type variable used only in an auxiliary port would let `Foo(Nat.zero)
+/// ~ Bool.true` through, so the signature is rejected.
+#[test]
+fn lone_type_var() {
+    let output = golden("lone_type_var", "lone_type_var", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// Duals of type variables, and type variables used more than twice.
+#[test]
+fn type_vars() {
+    let output = golden("type_vars", "type_vars", &[]);
+    assert_eq!(output.status.code(), Some(1));
+    golden("type_vars", "type_vars_desugared", &["--show-desugared"]);
+}
+
+/// Holes are reported with the type they expect, and don't keep a net from
+/// being reduced.
+#[test]
+fn holes() {
+    let output = golden("holes", "holes", &[]);
+    assert!(output.status.success());
+}
+
+/// Stuck pairs are explained by the annotations they come from, with the
+/// types as they are written.
+#[test]
+fn origins() {
+    let output = golden("origins", "origins", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// `Check` blocks report one diagnostic for each failure.
+#[test]
+fn check() {
+    let output = golden("check", "check", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// `trace`, `strategy` and `fuel` options of `Reduce`. Running out of fuel
+/// is only a warning.
+#[test]
+fn reduce_options() {
+    let output = golden("reduce_options", "reduce_options", &[]);
+    assert!(output.status.success());
+}
+
+#[test]
+fn reduce_bad_option() {
+    let output = golden("reduce_bad_option", "reduce_bad_option", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn macro_in_tree() {
+    let output = golden("macro_in_tree", "macro_in_tree", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// A Reduce block sees the rules further down the file, unless
+/// `--rules-so-far` is given.
+#[test]
+fn reduce_deferred() {
+    let output = golden("reduce_deferred", "reduce_deferred", &[]);
+    assert!(output.status.success());
+    golden(
+        "reduce_deferred",
+        "reduce_deferred_so_far",
+        &["--rules-so-far"],
+    );
+}
+
+#[test]
+fn impl_missing_space() {
+    let output = golden("impl_missing_space", "impl_missing_space", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// No type in `examples/main.itt` is above or below all others, so it has
+/// no `Top` or `Bot`.
+#[test]
+fn main_lattice() {
+    let output = run(&["--lattice", "examples/main.itt"], "");
+    let expected = std::fs::read_to_string(path("tests/golden/main_lattice.out")).unwrap();
+    assert_eq!(stdout(&output), expected);
+}
+
+/// The stubs are holes, so a file with its stubs appended passes the checks
+/// with a warning for each hole.
+#[test]
+fn stubs() {
+    let output = golden("stubs", "stubs", &["--stubs"]);
+    assert_eq!(output.status.code(), Some(1));
+    let file = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("stubs.itt");
+    std::fs::copy(path("tests/golden/stubs.itt"), &file).unwrap();
+    let file = file.to_str().unwrap();
+    run(&["--stubs-file", file, file], "");
+    let output = run(&[file], "");
+    let expected = std::fs::read_to_string(path("tests/golden/stubs_appended.out")).unwrap();
+    assert_eq!(stdout(&output), expected);
+    assert!(output.status.success());
+}
+
+#[test]
+fn trace_rules() {
+    let output = golden("trace_rules", "trace_rules", &[]);
+    assert!(output.status.success());
+    golden("trace_rules", "trace_rules_all", &["--trace-rules"]);
+}
+
+/// `Bool.and` has auxiliary ports, and is still checked for completeness.
+#[test]
+fn port_types() {
+    let output = golden("port_types", "port_types", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// Instances of `List(a)` are grouped under the type constructor `List`.
+#[test]
+fn parametric_completeness() {
+    let output = golden("parametric_completeness", "parametric_completeness", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// The missing rule is found even though `Bool.not` is named before `Bool`
+/// and its constructors.
+#[test]
+fn completeness_order() {
+    let output = golden("completeness_order", "completeness_order", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn inverse() {
+    let output = golden("inverse", "inverse", &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// Signatures are checked as the annotator rules they stand for.
+#[test]
+fn signatures() {
+    let output = golden("signatures", "empty", &[]);
+    assert!(output.status.success());
+    golden("signatures", "signatures", &["--show-desugared"]);
+}
+
+#[test]
+fn data() {
+    let output = golden("data", "data", &[]);
+    assert_eq!(output.status.code(), Some(1));
+    golden("data", "data_desugared", &["--show-desugared"]);
+}
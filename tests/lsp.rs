@@ -0,0 +1,99 @@
+//! Drives the language server with a scripted client over stdio.
+
+mod common;
+
+use common::{run, stdout};
+
+const URI: &str = "file:///test.itt";
+
+const DOCUMENT: &str = r#":(a b) ~ :(a b)\n::(a) ~ ::(a)\nData[Bool = Bool.true | Bool.false]\nBool.not : ~Bool(Bool)\nBool.not(Bool.false) ~ Bool.true\n"#;
+
+fn message(body: String) -> String {
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+fn request(id: u64, method: &str, params: &str) -> String {
+    message(format!(
+        r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#,
+        id, method, params
+    ))
+}
+
+fn notification(method: &str, params: &str) -> String {
+    message(format!(
+        r#"{{"jsonrpc":"2.0","method":"{}","params":{}}}"#,
+        method, params
+    ))
+}
+
+fn at(line: u64, character: u64) -> String {
+    format!(
+        r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}"#,
+        URI, line, character
+    )
+}
+
+/// Splits the server's output into message bodies.
+fn messages(mut output: &str) -> Vec<String> {
+    let mut bodies = vec![];
+    while let Some((header, rest)) = output.split_once("\r\n\r\n") {
+        let length: usize = header
+            .trim_start_matches("Content-Length: ")
+            .parse()
+            .unwrap();
+        bodies.push(rest[..length].to_string());
+        output = &rest[length..];
+    }
+    bodies
+}
+
+#[test]
+fn scripted_session() {
+    let script = [
+        request(1, "initialize", "{}"),
+        notification("initialized", "{}"),
+        notification(
+            "textDocument/didOpen",
+            &format!(
+                r#"{{"textDocument":{{"uri":"{}","languageId":"itt","version":1,"text":"{}"}}}}"#,
+                URI, DOCUMENT
+            ),
+        ),
+        request(2, "textDocument/hover", &at(3, 2)),
+        request(3, "textDocument/definition", &at(4, 2)),
+        request(
+            4,
+            "textDocument/documentSymbol",
+            &format!(r#"{{"textDocument":{{"uri":"{}"}}}}"#, URI),
+        ),
+        request(5, "unknown/method", "{}"),
+        request(6, "shutdown", "null"),
+        notification("exit", "null"),
+    ];
+    let output = run(&["lsp"], &script.concat());
+    assert!(output.status.success());
+    let messages = messages(&stdout(&output));
+    assert_eq!(messages.len(), 7, "{:#?}", messages);
+    assert!(messages[0].starts_with(r#"{"jsonrpc":"2.0","id":1,"result":{"capabilities""#));
+    // The missing `Bool.not ~ Bool.false` rule, reported at `Bool.false`.
+    assert!(messages[1].contains(r#""method":"textDocument/publishDiagnostics""#));
+    assert!(messages[1].contains(
+        r#""range":{"start":{"line":2,"character":24},"end":{"line":2,"character":34}},"severity":1"#
+    ));
+    assert!(messages[1].contains("but interaction Bool.false ~ Bool.not isn't"));
+    assert!(messages[2].contains(r#"type: `~Bool(Bool)`"#));
+    // The signature of `Bool.not` and its rule.
+    assert!(
+        messages[3].contains(
+            r#""range":{"start":{"line":3,"character":0},"end":{"line":3,"character":22}}"#
+        )
+    );
+    assert!(
+        messages[3].contains(
+            r#""range":{"start":{"line":4,"character":0},"end":{"line":4,"character":32}}"#
+        )
+    );
+    assert!(messages[4].contains(r#"{"name":"Bool.not ~ Bool.true","kind":12"#));
+    assert!(messages[5].contains(r#""error":{"code":-32601"#));
+    assert_eq!(messages[6], r#"{"jsonrpc":"2.0","id":6,"result":null}"#);
+}
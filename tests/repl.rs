@@ -0,0 +1,33 @@
+mod common;
+
+use common::{run, stdout};
+
+#[test]
+fn net_with_unknown_agent() {
+    let output = run(
+        &["repl", "examples/main.itt"],
+        "~(x) = Baz(y)\n:normal\n:quit\n",
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout(&output).contains("x = ~Baz(~(?"));
+}
+
+#[test]
+fn type_of_agent_named_at_prompt() {
+    let output = run(
+        &["repl", "examples/main.itt"],
+        "x = Baz(y)\n:type Baz\n:quit\n",
+    );
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Baz has no type"));
+}
+
+#[test]
+fn type_with_auxiliary_ports() {
+    let output = run(&["repl", "examples/main.itt"], ":type Bool.and\n:quit\n");
+    assert!(stdout(&output).contains("Bool.and : ~Bool(~Bool Bool)"));
+}